@@ -1,6 +1,8 @@
 use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
 use serde_json::json;
-use snowflake_id_worker::create_routes;
+use snowflake_id_worker::{
+    create_routes, create_routes_with, BitLayout, GeneratorKind, Worker, WorkerConfig,
+};
 use warp::test::request;
 
 fn bench_single_generate(c: &mut Criterion) {
@@ -22,12 +24,17 @@ fn bench_single_generate(c: &mut Criterion) {
 fn bench_batch_generate(c: &mut Criterion) {
     let mut group = c.benchmark_group("batch_generate");
 
-    for size in [10, 100, 1_000, 10_000, 100_000, 1_000_000, 10_000_000].iter() {
+    for size in [10u64, 100, 1_000, 10_000, 100_000, 1_000_000, 10_000_000].iter() {
         group.bench_with_input(BenchmarkId::new("batch", size), size, |b, &size| {
             let payload = json!({"count": size});
+            // NOTE(ayubun): the largest batches are well over the default MAX_BATCH_SIZE
+            let config = WorkerConfig::builder()
+                .max_batch_size(size)
+                .build()
+                .unwrap();
             b.iter(|| {
                 tokio::runtime::Runtime::new().unwrap().block_on(async {
-                    let routes = create_routes();
+                    let routes = create_routes_with(config.clone());
                     let resp = request()
                         .method("POST")
                         .path("/generate")
@@ -129,6 +136,71 @@ fn bench_concurrent_batch_generates(c: &mut Criterion) {
     group.finish();
 }
 
+fn worker_config(kind: GeneratorKind) -> WorkerConfig {
+    WorkerConfig::builder()
+        .generator_kind(kind)
+        .build()
+        .unwrap()
+}
+
+// NOTE(ayubun): this compares the lock-free generator against the mutex one, both straight
+// through the library (so only the generators are measured) and through the HTTP API
+fn bench_generator_kinds(c: &mut Criterion) {
+    let mut group = c.benchmark_group("generator_kinds");
+    for kind in [GeneratorKind::Atomic, GeneratorKind::Mutex] {
+        for threads in [1, 2, 4, 8, 16].iter() {
+            group.bench_with_input(
+                BenchmarkId::new(format!("{kind:?} threads"), threads),
+                threads,
+                |b, &threads| {
+                    let worker = Worker::new(worker_config(kind));
+                    b.iter(|| {
+                        std::thread::scope(|scope| {
+                            for _ in 0..threads {
+                                let worker = worker.clone();
+                                scope.spawn(move || {
+                                    for _ in 0..1_000 {
+                                        black_box(worker.generate().unwrap());
+                                    }
+                                });
+                            }
+                        })
+                    })
+                },
+            );
+        }
+
+        group.bench_function(BenchmarkId::new(format!("{kind:?} http"), 100), |b| {
+            let routes = create_routes_with(worker_config(kind));
+            let payload = json!({"count": 1});
+            b.iter(|| {
+                tokio::runtime::Runtime::new().unwrap().block_on(async {
+                    let mut handles = Vec::new();
+                    for _ in 0..100 {
+                        let routes_clone = routes.clone();
+                        let payload_clone = payload.clone();
+                        handles.push(tokio::spawn(async move {
+                            request()
+                                .method("POST")
+                                .path("/generate")
+                                .json(&payload_clone)
+                                .reply(&routes_clone)
+                                .await
+                        }));
+                    }
+
+                    let mut responses = Vec::new();
+                    for handle in handles {
+                        responses.push(handle.await.unwrap());
+                    }
+                    black_box(responses)
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
 // NOTE(ayubun): These aren't really that important but why not ╮ (. ❛ ᴗ ❛.) ╭
 fn bench_http_error_handling(c: &mut Criterion) {
     let mut group = c.benchmark_group("http_error_handling");
@@ -183,12 +255,55 @@ fn bench_http_error_handling(c: &mut Criterion) {
     group.finish();
 }
 
+// NOTE(ayubun): with the default layout, the generator is capped at 4096 IDs per millisecond,
+// which hides the cost of rendering the response. this uses a layout with a huge sequence
+// instead, so that the difference between the formats actually shows up
+fn bench_response_formats(c: &mut Criterion) {
+    let config = WorkerConfig::builder()
+        .layout(BitLayout {
+            timestamp_bits: 41,
+            data_center_id_bits: 0,
+            worker_id_bits: 0,
+            sequence_bits: 22,
+        })
+        .build()
+        .unwrap();
+    let mut group = c.benchmark_group("response_formats");
+    for size in [1_000, 10_000, 100_000].iter() {
+        for (format, accept) in [
+            ("json", "application/json"),
+            ("binary", "application/octet-stream"),
+        ] {
+            group.bench_with_input(BenchmarkId::new(format, size), size, |b, &size| {
+                let runtime = tokio::runtime::Runtime::new().unwrap();
+                let routes = create_routes_with(config.clone());
+                let payload = json!({"count": size});
+                b.iter(|| {
+                    runtime.block_on(async {
+                        let resp = request()
+                            .method("POST")
+                            .path("/generate")
+                            .header("accept", accept)
+                            .json(&payload)
+                            .reply(&routes)
+                            .await;
+                        black_box(resp)
+                    })
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
 criterion_group!(
     benches,
     bench_single_generate,
     bench_batch_generate,
     bench_concurrent_single_generates,
     bench_concurrent_batch_generates,
+    bench_generator_kinds,
+    bench_response_formats,
     bench_http_error_handling
 );
 criterion_main!(benches);
@@ -0,0 +1,8 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // NOTE(ayubun): protox compiles the proto in pure rust, so building doesn't need `protoc`
+    // installed (which is especially nice for the alpine docker build)
+    let file_descriptors = protox::compile(["proto/snowflake.proto"], ["proto"])?;
+    tonic_build::configure().compile_fds(file_descriptors)?;
+    println!("cargo:rerun-if-changed=proto");
+    Ok(())
+}
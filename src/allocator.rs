@@ -0,0 +1,894 @@
+//! Claims a `(DATA_CENTER_ID, WORKER_ID)` pair from a shared store with a TTL lease, for
+//! `WORKER_ID=FROM_LEASE`.
+//!
+//! The lease is renewed in the background. If it's lost, or can't be renewed before it expires,
+//! the worker stops generating IDs, since another worker may have claimed the same pair by then.
+//!
+//! Every renewal also records a high-water mark in the lease, which the next worker to claim the
+//! same pair waits for its clock to pass, so that a successor with a lagging clock can't reissue
+//! IDs.
+
+use std::{
+    hash::{BuildHasher, RandomState},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, OnceLock, Weak,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use redis::aio::{ConnectionManager, ConnectionManagerConfig};
+
+use crate::{clock::ClockMonitor, ConfigError, WorkerConfig, WorkerError};
+
+/// Errors from a [`WorkerIdAllocator`] backend, i.e. when the store can't be reached.
+pub type AllocatorError = Box<dyn std::error::Error + Send + Sync>;
+
+/// A `(DATA_CENTER_ID, WORKER_ID)` pair, which must only ever be used by one worker at a time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Identity {
+    pub data_center_id: u64,
+    pub worker_id: u64,
+}
+
+/// What [`WorkerIdAllocator::try_claim`] found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Claim {
+    /// Someone else already holds the identity
+    Taken,
+    /// The identity is ours now. `high_water_mark` is the last one recorded by whoever held it
+    /// before, if anyone did
+    Claimed { high_water_mark: Option<SystemTime> },
+}
+
+/// A store that workers claim their [`Identity`] from.
+///
+/// Claims are made by an `owner` (a string that's unique to each worker process) and expire after
+/// `ttl`, unless they're renewed. Implementations must make each of these atomic, i.e. two owners
+/// can never both successfully claim the same identity while the first claim is still alive.
+///
+/// Each identity also has a high-water mark, which is no earlier than any ID its holders may have
+/// issued. It must outlive the claims themselves (including expired and released ones), so that
+/// the next holder can wait for its clock to pass it.
+#[async_trait]
+pub trait WorkerIdAllocator: Send + Sync {
+    /// Claims `identity` for `ttl`, unless someone else already holds it. A successful claim must
+    /// also move the high-water mark up to at least `ttl` from now, since the new holder can issue
+    /// IDs until then without renewing.
+    async fn try_claim(
+        &self,
+        identity: Identity,
+        owner: &str,
+        ttl: Duration,
+    ) -> Result<Claim, AllocatorError>;
+
+    /// Extends a claim by `owner` to expire `ttl` from now and records `high_water_mark`,
+    /// returning `false` if the claim has expired or belongs to someone else.
+    async fn renew(
+        &self,
+        identity: Identity,
+        owner: &str,
+        ttl: Duration,
+        high_water_mark: SystemTime,
+    ) -> Result<bool, AllocatorError>;
+
+    /// Gives up a claim by `owner` with a final `high_water_mark`, so that another worker can take
+    /// it right away. Does nothing if the claim belongs to someone else.
+    async fn release(
+        &self,
+        identity: Identity,
+        owner: &str,
+        high_water_mark: SystemTime,
+    ) -> Result<(), AllocatorError>;
+}
+
+// NOTE(ayubun): every claim has to check who holds the key and act on it atomically, which redis
+// can only do with a script. the keys never expire by themselves, since the high-water mark has to
+// outlive the lease, so the expiry is kept (in the store's own time) alongside the owner instead.
+// keys that aren't hashes are left over from older versions, which are still holding onto them
+// claiming reserves the high-water mark up to the end of the lease right away (and hands back the
+// previous one to wait for), since the first renewal is a third of a TTL away and the worker could
+// issue IDs and crash before then
+const CLAIM_SCRIPT: &str = r#"
+local time = redis.call("TIME")
+local now = time[1] * 1000 + math.floor(time[2] / 1000)
+if redis.call("EXISTS", KEYS[1]) == 1 and redis.call("TYPE", KEYS[1]).ok ~= "hash" then return -1 end
+local lease = redis.call("HMGET", KEYS[1], "owner", "expires_at", "high_water_mark")
+if lease[1] and tonumber(lease[2]) > now then return -1 end
+local mark = tonumber(lease[3]) or 0
+redis.call("HSET", KEYS[1], "owner", ARGV[1], "expires_at", now + ARGV[2], "high_water_mark", math.max(mark, now + ARGV[2]))
+return mark
+"#;
+const RENEW_SCRIPT: &str = r#"
+local time = redis.call("TIME")
+local now = time[1] * 1000 + math.floor(time[2] / 1000)
+local lease = redis.call("HMGET", KEYS[1], "owner", "expires_at")
+if lease[1] ~= ARGV[1] or tonumber(lease[2]) <= now then return 0 end
+redis.call("HSET", KEYS[1], "expires_at", now + ARGV[2], "high_water_mark", ARGV[3])
+return 1
+"#;
+const RELEASE_SCRIPT: &str = r#"
+if redis.call("HGET", KEYS[1], "owner") ~= ARGV[1] then return 0 end
+redis.call("HSET", KEYS[1], "expires_at", 0, "high_water_mark", ARGV[2])
+return 1
+"#;
+
+/// Milliseconds since the unix epoch, which is how high-water marks are kept in the store.
+fn unix_ms(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|since| since.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// A [`WorkerIdAllocator`] backed by Redis (or anything that speaks its protocol and supports
+/// `EVAL`, i.e. Valkey or KeyDB).
+///
+/// Each identity is a hash named `{key_prefix}:{data_center_id}:{worker_id}`, with the `owner` of
+/// the claim, when it `expires_at` and its `high_water_mark` (both in milliseconds since the unix
+/// epoch).
+#[derive(Clone)]
+pub struct RedisAllocator {
+    connection: ConnectionManager,
+    key_prefix: String,
+}
+
+impl RedisAllocator {
+    /// Connects to the store at `url`, i.e. `redis://redis:6379/0`. Requests that take longer
+    /// than `timeout` fail, so that a hanging store can't hold up renewals.
+    pub async fn connect(
+        url: &str,
+        key_prefix: impl Into<String>,
+        timeout: Duration,
+    ) -> Result<RedisAllocator, AllocatorError> {
+        let client = redis::Client::open(url)?;
+        let config = ConnectionManagerConfig::new()
+            .set_connection_timeout(timeout)
+            .set_response_timeout(timeout)
+            .set_number_of_retries(1);
+        let connection = ConnectionManager::new_with_config(client, config).await?;
+        Ok(RedisAllocator {
+            connection,
+            key_prefix: key_prefix.into(),
+        })
+    }
+
+    fn key(&self, identity: Identity) -> String {
+        format!(
+            "{}:{}:{}",
+            self.key_prefix, identity.data_center_id, identity.worker_id
+        )
+    }
+}
+
+#[async_trait]
+impl WorkerIdAllocator for RedisAllocator {
+    async fn try_claim(
+        &self,
+        identity: Identity,
+        owner: &str,
+        ttl: Duration,
+    ) -> Result<Claim, AllocatorError> {
+        let high_water_mark_ms: i64 = redis::cmd("EVAL")
+            .arg(CLAIM_SCRIPT)
+            .arg(1)
+            .arg(self.key(identity))
+            .arg(owner)
+            .arg(ttl.as_millis() as u64)
+            .query_async(&mut self.connection.clone())
+            .await?;
+        Ok(match high_water_mark_ms {
+            -1 => Claim::Taken,
+            0 => Claim::Claimed {
+                high_water_mark: None,
+            },
+            ms => Claim::Claimed {
+                high_water_mark: Some(UNIX_EPOCH + Duration::from_millis(ms as u64)),
+            },
+        })
+    }
+
+    async fn renew(
+        &self,
+        identity: Identity,
+        owner: &str,
+        ttl: Duration,
+        high_water_mark: SystemTime,
+    ) -> Result<bool, AllocatorError> {
+        let renewed: i64 = redis::cmd("EVAL")
+            .arg(RENEW_SCRIPT)
+            .arg(1)
+            .arg(self.key(identity))
+            .arg(owner)
+            .arg(ttl.as_millis() as u64)
+            .arg(unix_ms(high_water_mark))
+            .query_async(&mut self.connection.clone())
+            .await?;
+        Ok(renewed == 1)
+    }
+
+    async fn release(
+        &self,
+        identity: Identity,
+        owner: &str,
+        high_water_mark: SystemTime,
+    ) -> Result<(), AllocatorError> {
+        let _: i64 = redis::cmd("EVAL")
+            .arg(RELEASE_SCRIPT)
+            .arg(1)
+            .arg(self.key(identity))
+            .arg(owner)
+            .arg(unix_ms(high_water_mark))
+            .query_async(&mut self.connection.clone())
+            .await?;
+        Ok(())
+    }
+}
+
+/// A claimed [`Identity`], which a [`Worker`](crate::Worker) can only generate IDs with while
+/// [`Lease::is_valid`].
+pub(crate) struct Lease {
+    identity: Identity,
+    owner: String,
+    allocator: Arc<dyn WorkerIdAllocator>,
+    ttl: Duration,
+    claimed_at: Instant,
+    // NOTE(ayubun): milliseconds after `claimed_at`, so that it fits into an atomic
+    valid_until_ms: AtomicU64,
+    lost: AtomicBool,
+    /// The high-water mark left behind by the previous holder, or `UNIX_EPOCH`
+    restored_mark: SystemTime,
+    /// Where the worker records the last issued timestamp, and its `EPOCH`
+    issued: OnceLock<(Arc<ClockMonitor>, SystemTime)>,
+}
+
+impl Lease {
+    pub(crate) fn identity(&self) -> Identity {
+        self.identity
+    }
+
+    /// The high-water mark that the previous holder of the identity left behind, if any.
+    pub(crate) fn restored_mark(&self) -> Option<SystemTime> {
+        (self.restored_mark > UNIX_EPOCH).then_some(self.restored_mark)
+    }
+
+    /// Starts recording the timestamps issued by the worker with `clock_monitor` into the
+    /// lease's high-water mark.
+    pub(crate) fn track(&self, clock_monitor: Arc<ClockMonitor>, epoch: SystemTime) {
+        let _ = self.issued.set((clock_monitor, epoch));
+    }
+
+    /// The latest time that IDs were issued at with this identity, by us or the previous holder.
+    fn last_issued(&self) -> SystemTime {
+        let issued = self.issued.get().and_then(|(clock_monitor, epoch)| {
+            let last_issued_ms = clock_monitor.last_issued_ms();
+            (last_issued_ms >= 0).then(|| *epoch + Duration::from_millis(last_issued_ms as u64))
+        });
+        issued.map_or(self.restored_mark, |issued| issued.max(self.restored_mark))
+    }
+
+    /// Whether the worker can still be sure that nobody else holds its identity.
+    pub(crate) fn is_valid(&self) -> bool {
+        !self.is_lost()
+            && (self.claimed_at.elapsed().as_millis() as u64)
+                < self.valid_until_ms.load(Ordering::Relaxed)
+    }
+
+    /// Whether the store has told us that the identity is gone for good (as opposed to the lease
+    /// just not having been renewed in time, which can still recover).
+    pub(crate) fn is_lost(&self) -> bool {
+        self.lost.load(Ordering::Relaxed)
+    }
+
+    // NOTE(ayubun): the store starts the TTL when it receives the request, which is always after
+    // `sent_at`, so counting from `sent_at` is on the safe side. the 10% margin covers the clocks
+    // of the worker and the store not ticking at quite the same rate
+    fn extend(&self, sent_at: Instant) {
+        let ttl = self.ttl - self.ttl / 10;
+        let valid_until = sent_at.saturating_duration_since(self.claimed_at) + ttl;
+        self.valid_until_ms
+            .store(valid_until.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Gives the identity back to the store, so that a replacement worker doesn't have to wait for
+    /// the lease to expire.
+    pub(crate) async fn release(&self) {
+        self.lost.store(true, Ordering::Relaxed);
+        // NOTE(ayubun): no more IDs can be issued once the lease is lost, so the mark can be moved
+        // back from the renewal's reservation to what was actually issued
+        let mark = self.last_issued();
+        match self
+            .allocator
+            .release(self.identity, &self.owner, mark)
+            .await
+        {
+            Ok(()) => tracing::info!(?self.identity, "released worker ID lease"),
+            Err(err) => {
+                tracing::warn!(?self.identity, error = %err, "failed to release worker ID lease")
+            }
+        }
+    }
+}
+
+/// Renews `lease` every third of its TTL, for as long as anything still holds onto it.
+async fn keep_renewed(lease: Weak<Lease>) {
+    let Some(interval) = lease
+        .upgrade()
+        .map(|lease| (lease.ttl / 3).max(Duration::from_millis(1)))
+    else {
+        return;
+    };
+    loop {
+        tokio::time::sleep(interval).await;
+        let Some(lease) = lease.upgrade() else {
+            return;
+        };
+        if lease.is_lost() {
+            return;
+        }
+
+        // NOTE(ayubun): reserving a full TTL ahead means that the mark covers every ID we can
+        // issue before the lease expires, even if this is the last renewal that gets through
+        let mark = lease.last_issued().max(SystemTime::now()) + lease.ttl;
+        let sent_at = Instant::now();
+        match lease
+            .allocator
+            .renew(lease.identity, &lease.owner, lease.ttl, mark)
+            .await
+        {
+            Ok(true) => {
+                if !lease.is_valid() {
+                    tracing::info!(?lease.identity, "renewed worker ID lease, resuming");
+                }
+                lease.extend(sent_at);
+            }
+            Ok(false) => {
+                lease.lost.store(true, Ordering::Relaxed);
+                tracing::error!(
+                    ?lease.identity,
+                    "LOST THE WORKER ID LEASE! another worker may be using the same worker ID, \
+                     so no more IDs will be generated"
+                );
+                return;
+            }
+            // NOTE(ayubun): the lease might still be ours, so keep trying. generation stops on its
+            // own once the lease runs out, and picks back up if a renewal gets through in time
+            Err(err) => tracing::warn!(
+                ?lease.identity,
+                error = %err,
+                expired = !lease.is_valid(),
+                "failed to renew worker ID lease"
+            ),
+        }
+    }
+}
+
+/// A string that's unique to this process, so that the store can tell our claims apart from
+/// everyone else's (even after a restart onto the same host).
+fn owner() -> String {
+    let hostname = hostname::get()
+        .map(|os| os.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| "localhost".to_string());
+    let started_ns = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_nanos())
+        .unwrap_or(0);
+    format!("{hostname}/{}/{started_ns}", std::process::id())
+}
+
+/// Claims the first free identity that fits into the layout of `config`, and starts renewing it in
+/// the background. Only worker IDs within `config.data_center_id` are tried unless
+/// `lease_data_center_id` is set.
+pub(crate) async fn claim(
+    allocator: Arc<dyn WorkerIdAllocator>,
+    config: &WorkerConfig,
+) -> Result<Arc<Lease>, WorkerError> {
+    let data_center_ids = if config.lease_data_center_id {
+        0..=config.layout.max_data_center_id()
+    } else {
+        config.data_center_id..=config.data_center_id
+    };
+    let candidates: Vec<Identity> = data_center_ids
+        .flat_map(|data_center_id| {
+            (0..=config.layout.max_worker_id()).map(move |worker_id| Identity {
+                data_center_id,
+                worker_id,
+            })
+        })
+        .collect();
+
+    // NOTE(ayubun): starting somewhere random means that workers starting at the same time don't
+    // all fight over the lowest IDs
+    let owner = owner();
+    let start = RandomState::new().hash_one(&owner) as usize % candidates.len();
+    let ttl = config.lease_ttl;
+    for &identity in candidates[start..].iter().chain(&candidates[..start]) {
+        let sent_at = Instant::now();
+        let claim = allocator
+            .try_claim(identity, &owner, ttl)
+            .await
+            .map_err(|err| WorkerError::LeaseStore {
+                message: err.to_string(),
+            })?;
+        let Claim::Claimed { high_water_mark } = claim else {
+            continue;
+        };
+
+        let lease = Arc::new(Lease {
+            identity,
+            owner,
+            allocator,
+            ttl,
+            claimed_at: sent_at,
+            valid_until_ms: AtomicU64::new(0),
+            lost: AtomicBool::new(false),
+            restored_mark: high_water_mark.unwrap_or(UNIX_EPOCH),
+            issued: OnceLock::new(),
+        });
+        lease.extend(sent_at);
+        tokio::spawn(keep_renewed(Arc::downgrade(&lease)));
+        tracing::info!(?identity, owner = lease.owner, "claimed worker ID lease");
+        return Ok(lease);
+    }
+    Err(ConfigError::NoFreeWorkerId {
+        candidates: candidates.len(),
+    }
+    .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BitLayout, GenerateError, HighWaterMarkPolicy, Worker};
+    use std::{
+        collections::{HashMap, HashSet},
+        sync::Mutex,
+    };
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+        task::JoinSet,
+    };
+    use warp::test::request;
+
+    type Keys = Arc<Mutex<HashMap<String, StoredLease>>>;
+
+    /// What [`RedisAllocator`]'s scripts keep in each hash.
+    struct StoredLease {
+        owner: String,
+        expires_at: Instant,
+        high_water_mark_ms: u64,
+    }
+
+    /// An in-process stand-in for redis, which only understands the commands that
+    /// [`RedisAllocator`] sends.
+    struct FakeRedis {
+        url: String,
+        keys: Keys,
+        server: tokio::task::JoinHandle<()>,
+    }
+
+    impl FakeRedis {
+        async fn start() -> FakeRedis {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("redis://{}", listener.local_addr().unwrap());
+            let keys = Keys::default();
+            let server_keys = keys.clone();
+            let server = tokio::spawn(async move {
+                // NOTE(ayubun): connections live in the join set, so that aborting the server
+                // also hangs up on every client
+                let mut connections = JoinSet::new();
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    connections.spawn(serve_connection(stream, server_keys.clone()));
+                }
+            });
+            FakeRedis { url, keys, server }
+        }
+
+        /// Hands a key over to someone else, as if our lease had expired and been claimed.
+        fn steal(&self, key: &str) {
+            let mut keys = self.keys.lock().unwrap();
+            let lease = keys.get_mut(key).expect("key should be claimed");
+            lease.owner = "someone-else".to_string();
+        }
+
+        fn owner(&self, key: &str) -> Option<String> {
+            let keys = self.keys.lock().unwrap();
+            keys.get(key)
+                .filter(|lease| lease.expires_at > Instant::now())
+                .map(|lease| lease.owner.clone())
+        }
+
+        fn high_water_mark_ms(&self, key: &str) -> Option<u64> {
+            let keys = self.keys.lock().unwrap();
+            keys.get(key).map(|lease| lease.high_water_mark_ms)
+        }
+
+        /// Leaves a released lease behind on `key`, as if a previous worker had issued IDs up to
+        /// `high_water_mark`.
+        fn leave_released(&self, key: &str, high_water_mark: SystemTime) {
+            self.keys.lock().unwrap().insert(
+                key.to_string(),
+                StoredLease {
+                    owner: "previous".to_string(),
+                    expires_at: Instant::now(),
+                    high_water_mark_ms: unix_ms(high_water_mark),
+                },
+            );
+        }
+
+        /// Lets the lease on `key` run out without touching its high-water mark, as if its holder
+        /// had crashed.
+        fn expire(&self, key: &str) {
+            let mut keys = self.keys.lock().unwrap();
+            let lease = keys.get_mut(key).expect("key should be claimed");
+            lease.expires_at = Instant::now();
+        }
+
+        /// Stops answering, as if the store had gone down.
+        fn stop(&self) {
+            self.server.abort();
+        }
+    }
+
+    async fn serve_connection(stream: tokio::net::TcpStream, keys: Keys) {
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+        while let Some(command) = read_command(&mut reader).await {
+            let reply = execute(&keys, &command);
+            if writer.write_all(reply.as_bytes()).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    async fn read_line(reader: &mut BufReader<tokio::net::tcp::OwnedReadHalf>) -> Option<String> {
+        let mut line = String::new();
+        match reader.read_line(&mut line).await {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(line.trim_end().to_string()),
+        }
+    }
+
+    /// Reads a command, which clients always send as an array of bulk strings.
+    async fn read_command(
+        reader: &mut BufReader<tokio::net::tcp::OwnedReadHalf>,
+    ) -> Option<Vec<String>> {
+        let len: usize = read_line(reader).await?.strip_prefix('*')?.parse().ok()?;
+        let mut command = Vec::with_capacity(len);
+        for _ in 0..len {
+            // NOTE(ayubun): the scripts span several lines, so bulk strings have to be read by
+            // their length (plus the trailing CRLF)
+            let len: usize = read_line(reader).await?.strip_prefix('$')?.parse().ok()?;
+            let mut bulk = vec![0; len + 2];
+            reader.read_exact(&mut bulk).await.ok()?;
+            bulk.truncate(len);
+            command.push(String::from_utf8(bulk).ok()?);
+        }
+        Some(command)
+    }
+
+    fn execute(keys: &Keys, command: &[String]) -> String {
+        let mut keys = keys.lock().unwrap();
+        let now = Instant::now();
+        let ttl = |ms: &str| now + Duration::from_millis(ms.parse().unwrap());
+        match command.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+            ["EVAL", CLAIM_SCRIPT, "1", key, owner, ms] => {
+                let high_water_mark_ms = match keys.get(key) {
+                    Some(lease) if lease.expires_at > now => return ":-1\r\n".to_string(),
+                    Some(lease) => lease.high_water_mark_ms,
+                    None => 0,
+                };
+                let reserved_ms = unix_ms(SystemTime::now()) + ms.parse::<u64>().unwrap();
+                keys.insert(
+                    key.to_string(),
+                    StoredLease {
+                        owner: owner.to_string(),
+                        expires_at: ttl(ms),
+                        high_water_mark_ms: high_water_mark_ms.max(reserved_ms),
+                    },
+                );
+                format!(":{high_water_mark_ms}\r\n")
+            }
+            ["EVAL", RENEW_SCRIPT, "1", key, owner, ms, mark] => match keys.get_mut(key) {
+                Some(lease) if lease.owner == owner && lease.expires_at > now => {
+                    lease.expires_at = ttl(ms);
+                    lease.high_water_mark_ms = mark.parse().unwrap();
+                    ":1\r\n".to_string()
+                }
+                _ => ":0\r\n".to_string(),
+            },
+            ["EVAL", RELEASE_SCRIPT, "1", key, owner, mark] => match keys.get_mut(key) {
+                Some(lease) if lease.owner == owner => {
+                    lease.expires_at = now;
+                    lease.high_water_mark_ms = mark.parse().unwrap();
+                    ":1\r\n".to_string()
+                }
+                _ => ":0\r\n".to_string(),
+            },
+            _ => format!("-ERR unknown command {command:?}\r\n"),
+        }
+    }
+
+    async fn allocator(redis: &FakeRedis) -> Arc<dyn WorkerIdAllocator> {
+        Arc::new(
+            RedisAllocator::connect(&redis.url, "test", Duration::from_millis(100))
+                .await
+                .unwrap(),
+        )
+    }
+
+    fn config(ttl: Duration) -> WorkerConfig {
+        WorkerConfig::builder()
+            .layout(BitLayout {
+                timestamp_bits: 41,
+                data_center_id_bits: 1,
+                worker_id_bits: 1,
+                sequence_bits: 20,
+            })
+            .lease_data_center_id(true)
+            .lease_ttl(ttl)
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_redis_allocator() {
+        let redis = FakeRedis::start().await;
+        let allocator = allocator(&redis).await;
+        let identity = Identity {
+            data_center_id: 1,
+            worker_id: 2,
+        };
+        let ttl = Duration::from_secs(10);
+        let unclaimed = Claim::Claimed {
+            high_water_mark: None,
+        };
+
+        assert_eq!(
+            allocator.try_claim(identity, "a", ttl).await.unwrap(),
+            unclaimed
+        );
+        assert_eq!(redis.owner("test:1:2").as_deref(), Some("a"));
+        assert_eq!(
+            allocator.try_claim(identity, "b", ttl).await.unwrap(),
+            Claim::Taken
+        );
+        let mark = UNIX_EPOCH + Duration::from_millis(1420070400000);
+        assert!(allocator.renew(identity, "a", ttl, mark).await.unwrap());
+        assert!(!allocator.renew(identity, "b", ttl, mark).await.unwrap());
+        assert_eq!(redis.high_water_mark_ms("test:1:2"), Some(1420070400000));
+
+        // NOTE(ayubun): the mark outlives the claim, so that the next holder gets to see it
+        let mark = mark + Duration::from_millis(5);
+        allocator.release(identity, "b", mark).await.unwrap();
+        assert_eq!(redis.owner("test:1:2").as_deref(), Some("a"));
+        allocator.release(identity, "a", mark).await.unwrap();
+        assert_eq!(redis.owner("test:1:2"), None);
+        assert_eq!(
+            allocator.try_claim(identity, "b", ttl).await.unwrap(),
+            Claim::Claimed {
+                high_water_mark: Some(mark)
+            }
+        );
+
+        // NOTE(ayubun): an expired claim can be taken over, but not renewed by its old owner
+        let short = Duration::from_millis(50);
+        let other = Identity {
+            data_center_id: 0,
+            worker_id: 0,
+        };
+        assert_eq!(
+            allocator.try_claim(other, "a", short).await.unwrap(),
+            unclaimed
+        );
+        tokio::time::sleep(short * 2).await;
+        assert!(!allocator.renew(other, "a", ttl, mark).await.unwrap());
+        // NOTE(ayubun): the first claim reserved the mark up to the end of its lease, even though
+        // it was never renewed
+        assert!(matches!(
+            allocator.try_claim(other, "b", ttl).await.unwrap(),
+            Claim::Claimed {
+                high_water_mark: Some(_)
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_workers_claim_distinct_identities() {
+        let redis = FakeRedis::start().await;
+        let allocator = allocator(&redis).await;
+        let config = config(Duration::from_secs(10));
+
+        let mut workers = Vec::new();
+        for _ in 0..4 {
+            workers.push(
+                Worker::with_allocator(config.clone(), allocator.clone())
+                    .await
+                    .unwrap(),
+            );
+        }
+        let identities: HashSet<(u64, u64)> = workers
+            .iter()
+            .map(|worker| {
+                let decoded = worker.decode(worker.generate().unwrap()).unwrap();
+                (decoded.data_center_id, decoded.worker_id)
+            })
+            .collect();
+        assert_eq!(identities.len(), 4, "Every worker should get its own pair");
+
+        let result = Worker::with_allocator(config.clone(), allocator.clone()).await;
+        assert!(matches!(
+            result,
+            Err(WorkerError::Config(ConfigError::NoFreeWorkerId {
+                candidates: 4
+            }))
+        ));
+
+        // NOTE(ayubun): releasing a lease frees its pair up for the next worker
+        workers.pop().unwrap().release_lease().await;
+        assert!(Worker::with_allocator(config, allocator).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_successor_waits_for_the_high_water_mark() {
+        let redis = FakeRedis::start().await;
+        let allocator = allocator(&redis).await;
+        let config = config(Duration::from_secs(10));
+
+        // NOTE(ayubun): releasing a lease leaves the last issued timestamp behind
+        let worker = Worker::with_allocator(config.clone(), allocator.clone())
+            .await
+            .unwrap();
+        let decoded = worker.decode(worker.generate().unwrap()).unwrap();
+        worker.release_lease().await;
+        let key = format!("test:{}:{}", decoded.data_center_id, decoded.worker_id);
+        assert_eq!(
+            redis.high_water_mark_ms(&key),
+            Some(unix_ms(config.epoch) + decoded.timestamp_ms as u64)
+        );
+
+        // NOTE(ayubun): pretend that every previous holder's clock was ahead of ours
+        let leave_everything_released = |mark| {
+            for data_center_id in 0..2 {
+                for worker_id in 0..2 {
+                    redis.leave_released(&format!("test:{data_center_id}:{worker_id}"), mark);
+                }
+            }
+        };
+        let mark = SystemTime::now() + Duration::from_millis(200);
+        leave_everything_released(mark);
+        let worker = Worker::with_allocator(config.clone(), allocator.clone())
+            .await
+            .unwrap();
+        assert!(
+            SystemTime::now() > mark,
+            "Should wait for the clock to pass the mark"
+        );
+        let decoded = worker.decode(worker.generate().unwrap()).unwrap();
+        assert!(unix_ms(config.epoch) + decoded.timestamp_ms as u64 > unix_ms(mark));
+
+        // NOTE(ayubun): a mark far in the future should fail with the "fail" policy
+        leave_everything_released(SystemTime::now() + Duration::from_secs(60));
+        let config = WorkerConfig {
+            high_water_mark_policy: HighWaterMarkPolicy::Fail,
+            ..config
+        };
+        let result = Worker::with_allocator(config, allocator).await;
+        assert!(matches!(
+            result,
+            Err(WorkerError::Config(ConfigError::BehindHighWaterMark {
+                origin: "the worker ID lease",
+                ..
+            }))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_successor_waits_for_a_crashed_claim() {
+        let redis = FakeRedis::start().await;
+        let allocator = allocator(&redis).await;
+        let ttl = Duration::from_millis(500);
+        let config = config(ttl);
+
+        let mut workers = Vec::new();
+        for _ in 0..4 {
+            workers.push(
+                Worker::with_allocator(config.clone(), allocator.clone())
+                    .await
+                    .unwrap(),
+            );
+        }
+
+        // NOTE(ayubun): the worker crashes right after issuing an ID, well before its first
+        // renewal, so only the claim itself could have reserved a high-water mark
+        let crashed = workers.pop().unwrap();
+        let decoded = crashed.decode(crashed.generate().unwrap()).unwrap();
+        let issued_ms = unix_ms(config.epoch) + decoded.timestamp_ms as u64;
+        let key = format!("test:{}:{}", decoded.data_center_id, decoded.worker_id);
+        redis.expire(&key);
+        let mark_ms = redis.high_water_mark_ms(&key).unwrap();
+        assert!(mark_ms >= issued_ms, "The claim should reserve a mark");
+
+        let successor = Worker::with_allocator(config.clone(), allocator)
+            .await
+            .unwrap();
+        assert!(
+            unix_ms(SystemTime::now()) >= mark_ms,
+            "Should wait for the clock to pass the mark"
+        );
+        let successor_decoded = successor.decode(successor.generate().unwrap()).unwrap();
+        assert_eq!(
+            (
+                successor_decoded.data_center_id,
+                successor_decoded.worker_id
+            ),
+            (decoded.data_center_id, decoded.worker_id)
+        );
+        assert!(unix_ms(config.epoch) + successor_decoded.timestamp_ms as u64 > mark_ms);
+    }
+
+    #[tokio::test]
+    async fn test_generation_stops_when_lease_is_lost() {
+        let redis = FakeRedis::start().await;
+        let ttl = Duration::from_millis(300);
+        let config = WorkerConfig::builder()
+            .data_center_id(1)
+            .lease_ttl(ttl)
+            .build()
+            .unwrap();
+        let worker = Worker::with_allocator(config, allocator(&redis).await)
+            .await
+            .unwrap();
+        let routes = crate::routes(worker.clone());
+        let decoded = worker.decode(worker.generate().unwrap()).unwrap();
+        assert_eq!(decoded.data_center_id, 1, "DATA_CENTER_ID should be kept");
+
+        // NOTE(ayubun): renewals should keep the lease alive well past its TTL, and reserve the
+        // high-water mark ahead of anything the worker could issue before the lease expires
+        tokio::time::sleep(ttl * 2).await;
+        assert!(worker.generate().is_ok());
+        let key = format!("test:1:{}", decoded.worker_id);
+        let mark_ms = redis.high_water_mark_ms(&key).unwrap();
+        assert!(mark_ms > unix_ms(SystemTime::now()));
+
+        redis.steal(&key);
+        tokio::time::sleep(ttl / 2).await;
+        assert_eq!(worker.generate(), Err(GenerateError::LeaseLost));
+
+        let resp = request()
+            .method("POST")
+            .path("/generate")
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 503);
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body["type"], "urn:snowflake-id-worker:problem:lease-lost");
+        for path in ["/livez", "/readyz", "/health"] {
+            let resp = request().method("GET").path(path).reply(&routes).await;
+            assert_eq!(resp.status(), 503, "{path} should fail");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_generation_stops_when_store_is_unreachable() {
+        let redis = FakeRedis::start().await;
+        let ttl = Duration::from_millis(300);
+        let worker = Worker::with_allocator(config(ttl), allocator(&redis).await)
+            .await
+            .unwrap();
+        let routes = crate::routes(worker.clone());
+        assert!(worker.generate().is_ok());
+
+        redis.stop();
+        tokio::time::sleep(ttl).await;
+        assert_eq!(worker.generate(), Err(GenerateError::LeaseLost));
+
+        // NOTE(ayubun): the lease might come back, so the worker is only unready, not dead
+        let resp = request().method("GET").path("/livez").reply(&routes).await;
+        assert_eq!(resp.status(), 200);
+        let resp = request().method("GET").path("/readyz").reply(&routes).await;
+        assert_eq!(resp.status(), 503);
+    }
+}
@@ -0,0 +1,321 @@
+use std::{
+    hint::spin_loop,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    clock::{self, ClockMonitor, ClockRegressionError, ClockRegressionPolicy},
+    generator::{millis_since, BitLayout, SnowflakeParts},
+    metrics::Metrics,
+    GenerateError, WorkerConfig,
+};
+
+/// A lock-free snowflake ID generator.
+///
+/// The timestamp and sequence of the last issued ID are packed into a single `AtomicU64`, which
+/// is advanced with compare-and-swap. Callers never block each other, except when the sequence for
+/// the current millisecond is used up (or the clock moves backwards) and everyone has to wait.
+pub(crate) struct AtomicSnowflakeIdGenerator {
+    layout: BitLayout,
+    epoch: SystemTime,
+    data_center_id: u64,
+    worker_id: u64,
+    clock_regression_policy: ClockRegressionPolicy,
+    clock_regression_max_wait: Duration,
+    clock_monitor: Arc<ClockMonitor>,
+    metrics: Arc<Metrics>,
+    /// `(timestamp_ms << sequence_bits) | sequence` of the last issued ID
+    state: AtomicU64,
+}
+
+/// A run of consecutive sequence numbers claimed within a single millisecond.
+struct Claim {
+    timestamp_ms: i64,
+    first_sequence: u64,
+    count: u64,
+}
+
+impl AtomicSnowflakeIdGenerator {
+    pub(crate) fn from_config(config: &WorkerConfig) -> AtomicSnowflakeIdGenerator {
+        AtomicSnowflakeIdGenerator {
+            layout: config.layout,
+            epoch: config.epoch,
+            data_center_id: config.data_center_id,
+            worker_id: config.worker_id,
+            clock_regression_policy: config.clock_regression_policy,
+            clock_regression_max_wait: config.clock_regression_max_wait,
+            clock_monitor: Arc::new(ClockMonitor::new(config.epoch)),
+            metrics: Arc::new(Metrics::new(config.worker_id, config.data_center_id)),
+            state: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn clock_monitor(&self) -> Arc<ClockMonitor> {
+        self.clock_monitor.clone()
+    }
+
+    pub(crate) fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    #[inline(always)]
+    fn pack(&self, timestamp_ms: i64, sequence: u64) -> u64 {
+        (timestamp_ms as u64) << self.layout.sequence_bits | sequence
+    }
+
+    #[inline(always)]
+    fn unpack(&self, state: u64) -> (i64, u64) {
+        (
+            (state >> self.layout.sequence_bits) as i64,
+            state & self.layout.max_sequence(),
+        )
+    }
+
+    /// Makes sure that no IDs are issued at or below `mark_ms`, which may have been issued by a
+    /// previous run of the worker.
+    pub(crate) fn restore_high_water_mark(&self, mark_ms: i64) {
+        // NOTE(ayubun): marking the sequence as used up forces the next ID into a later
+        // millisecond, since we don't know which sequences were issued at the mark
+        let mark = self.pack(mark_ms, self.layout.max_sequence());
+        self.state.fetch_max(mark, Ordering::AcqRel);
+        self.clock_monitor.record_issued(mark_ms);
+    }
+
+    /// Pretends that the last ID was issued at `timestamp_ms`. Pretending that it was issued in
+    /// the future is the same as the clock having moved backwards since then.
+    #[cfg(test)]
+    pub(crate) fn pretend_last_issued_at(&self, timestamp_ms: i64) {
+        self.state
+            .store(self.pack(timestamp_ms, 0), Ordering::Release);
+        self.clock_monitor.record_issued(timestamp_ms);
+    }
+
+    /// Generates a new ID. See [`crate::generator::SnowflakeIdGenerator::generate`] for how
+    /// sequence exhaustion, clock regressions and timestamp overflows are handled.
+    pub(crate) fn generate(&self) -> Result<i64, GenerateError> {
+        let claim = self.claim(1)?;
+        Ok(self.compose(claim.timestamp_ms, claim.first_sequence))
+    }
+
+    /// Generates `count` IDs, claiming as many sequence numbers as possible with each
+    /// compare-and-swap.
+    pub(crate) fn generate_batch(&self, count: usize) -> Result<Vec<i64>, GenerateError> {
+        let mut ids = Vec::with_capacity(count);
+        while ids.len() < count {
+            let claim = self.claim((count - ids.len()) as u64)?;
+            let last_sequence = claim.first_sequence + claim.count;
+            ids.extend(
+                (claim.first_sequence..last_sequence)
+                    .map(|sequence| self.compose(claim.timestamp_ms, sequence)),
+            );
+        }
+        Ok(ids)
+    }
+
+    #[inline(always)]
+    fn compose(&self, timestamp_ms: i64, sequence: u64) -> i64 {
+        self.layout.compose(SnowflakeParts {
+            timestamp_ms,
+            data_center_id: self.data_center_id,
+            worker_id: self.worker_id,
+            sequence,
+        })
+    }
+
+    /// Claims between 1 and `wanted` consecutive sequence numbers within a single millisecond.
+    fn claim(&self, wanted: u64) -> Result<Claim, GenerateError> {
+        let max_sequence = self.layout.max_sequence();
+        let mut current = self.state.load(Ordering::Acquire);
+        let mut waiting_since = None;
+        loop {
+            let (last_timestamp_ms, last_sequence) = self.unpack(current);
+            let now_ms = millis_since(self.epoch);
+
+            let clock_is_behind = now_ms < last_timestamp_ms;
+            if clock_is_behind {
+                let drift_ms = last_timestamp_ms - now_ms;
+                if self
+                    .clock_monitor
+                    .record_regression(drift_ms, self.clock_regression_policy)
+                {
+                    self.metrics.clock_regressions.inc();
+                }
+                match self.clock_regression_policy {
+                    ClockRegressionPolicy::Wait
+                        if drift_ms as u128 <= self.clock_regression_max_wait.as_millis() =>
+                    {
+                        clock::wait_until(last_timestamp_ms, self.epoch);
+                        current = self.state.load(Ordering::Acquire);
+                        continue;
+                    }
+                    ClockRegressionPolicy::Hold => {}
+                    ClockRegressionPolicy::Wait | ClockRegressionPolicy::Fail => {
+                        return Err(ClockRegressionError { drift_ms }.into());
+                    }
+                }
+            } else {
+                self.clock_monitor.record_caught_up();
+            }
+
+            let (timestamp_ms, first_sequence) = if now_ms > last_timestamp_ms {
+                (now_ms, 0)
+            } else if last_sequence < max_sequence {
+                (last_timestamp_ms, last_sequence + 1)
+            } else if clock_is_behind {
+                // NOTE(ayubun): the clock is behind, so there's no point waiting for the next
+                // millisecond. instead, we borrow it from the future
+                (last_timestamp_ms + 1, 0)
+            } else {
+                // NOTE(ayubun): the sequence for this millisecond is used up, so we have to wait
+                // for the next one (or for another caller to move us onto it)
+                waiting_since.get_or_insert_with(Instant::now);
+                spin_loop();
+                current = self.state.load(Ordering::Acquire);
+                continue;
+            };
+
+            self.layout.check_timestamp(timestamp_ms)?;
+            let count = wanted.min(max_sequence - first_sequence + 1);
+            let next = self.pack(timestamp_ms, first_sequence + count - 1);
+            match self.state.compare_exchange_weak(
+                current,
+                next,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => {
+                    if let Some(waiting_since) = waiting_since {
+                        self.metrics
+                            .sequence_exhaustion_wait
+                            .observe(waiting_since.elapsed().as_secs_f64());
+                    }
+                    if first_sequence == 0 {
+                        self.clock_monitor.record_issued(timestamp_ms);
+                    }
+                    return Ok(Claim {
+                        timestamp_ms,
+                        first_sequence,
+                        count,
+                    });
+                }
+                Err(actual) => current = actual,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::HashSet, thread, time::UNIX_EPOCH};
+
+    fn atomic_generator(policy: ClockRegressionPolicy) -> AtomicSnowflakeIdGenerator {
+        let config = WorkerConfig::builder()
+            .worker_id(5)
+            .data_center_id(3)
+            .clock_regression_policy(policy, Duration::from_millis(10))
+            .build()
+            .unwrap();
+        AtomicSnowflakeIdGenerator::from_config(&config)
+    }
+
+    #[test]
+    fn test_concurrent_generates_are_unique() {
+        let generator = Arc::new(atomic_generator(ClockRegressionPolicy::Wait));
+
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let generator = generator.clone();
+                thread::spawn(move || {
+                    if i % 2 == 0 {
+                        (0..20_000)
+                            .map(|_| generator.generate().unwrap())
+                            .collect::<Vec<_>>()
+                    } else {
+                        generator.generate_batch(20_000).unwrap()
+                    }
+                })
+            })
+            .collect();
+
+        let mut all_ids = Vec::new();
+        for handle in handles {
+            let ids = handle.join().unwrap();
+            assert!(
+                ids.windows(2).all(|w| w[0] < w[1]),
+                "IDs should keep increasing"
+            );
+            all_ids.extend(ids);
+        }
+
+        let unique_ids: HashSet<i64> = all_ids.iter().cloned().collect();
+        assert_eq!(unique_ids.len(), 160_000, "All IDs should be unique");
+        for id in all_ids {
+            let parts = generator.layout.decompose(id);
+            assert_eq!(parts.worker_id, 5);
+            assert_eq!(parts.data_center_id, 3);
+        }
+    }
+
+    #[test]
+    fn test_clock_regression_policies() {
+        let generator = atomic_generator(ClockRegressionPolicy::Fail);
+        generator.pretend_last_issued_at(millis_since(UNIX_EPOCH) + 10_000);
+        assert!(generator.generate().is_err());
+        assert!(generator.generate_batch(10).is_err());
+        assert_eq!(generator.clock_monitor().events(), 1);
+
+        let generator = atomic_generator(ClockRegressionPolicy::Hold);
+        let last_timestamp_ms = millis_since(UNIX_EPOCH) + 10_000;
+        generator.pretend_last_issued_at(last_timestamp_ms);
+        let ids = generator.generate_batch(10_000).unwrap();
+        let unique_ids: HashSet<i64> = ids.iter().cloned().collect();
+        assert_eq!(unique_ids.len(), ids.len(), "All IDs should be unique");
+        assert!(ids
+            .iter()
+            .all(|&id| generator.layout.decompose(id).timestamp_ms >= last_timestamp_ms));
+
+        let generator = atomic_generator(ClockRegressionPolicy::Wait);
+        let last_timestamp_ms = millis_since(UNIX_EPOCH) + 5;
+        generator.pretend_last_issued_at(last_timestamp_ms);
+        let id = generator.generate().unwrap();
+        assert!(generator.layout.decompose(id).timestamp_ms >= last_timestamp_ms);
+    }
+
+    #[test]
+    fn test_generator_refuses_to_overflow_the_timestamp() {
+        // NOTE(ayubun): 10 timestamp bits only last for ~1 second, so a worker that started just
+        // before then runs out of timestamps almost right away
+        let config = WorkerConfig::builder()
+            .layout(BitLayout {
+                timestamp_bits: 10,
+                data_center_id_bits: 10,
+                worker_id_bits: 10,
+                sequence_bits: 33,
+            })
+            .epoch(SystemTime::now() - Duration::from_millis(1_000))
+            .build()
+            .unwrap();
+        let generator = AtomicSnowflakeIdGenerator::from_config(&config);
+        assert!(generator.generate().unwrap() > 0);
+
+        thread::sleep(Duration::from_millis(50));
+        let overflow = GenerateError::TimestampOverflow { timestamp_bits: 10 };
+        assert_eq!(generator.generate(), Err(overflow));
+        assert_eq!(generator.generate_batch(10), Err(overflow));
+    }
+
+    #[test]
+    fn test_restored_high_water_mark_is_never_reissued() {
+        let generator = atomic_generator(ClockRegressionPolicy::Wait);
+        let mark_ms = millis_since(UNIX_EPOCH);
+        generator.restore_high_water_mark(mark_ms);
+
+        let id = generator.generate().unwrap();
+        assert!(generator.layout.decompose(id).timestamp_ms > mark_ms);
+    }
+}
@@ -1,22 +1,469 @@
+//! A tiny HTTP client for checking on a running worker, i.e. from a Docker `HEALTHCHECK`.
+//!
+//! It exits with `0` if the worker is healthy, or with the [`Failure::exit_code`] of whatever went
+//! wrong otherwise.
+
 use std::{
-    env,
-    io::{Read, Write},
-    net::TcpStream,
+    fmt,
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    process::ExitCode,
+    time::{Duration, Instant},
 };
 
-fn main() {
-    let port = env::var("PORT")
-        .unwrap_or("8080".to_string())
-        .parse::<u16>()
-        .expect("valid port (u16)");
+use clap::Parser;
+use serde::de::DeserializeOwned;
+
+#[derive(Debug, clap::Parser)]
+struct Args {
+    #[arg(long, default_value = "127.0.0.1", env = "HEALTHCHECK_HOST")]
+    host: String,
+
+    // SHARED WITH THE WORKER, SO THAT THE HEALTHCHECK FOLLOWS IT TO WHATEVER PORT IT LISTENS ON
+    #[arg(long, default_value_t = 8080, env = "PORT")]
+    port: u16,
+
+    #[arg(long, default_value = "/health", env = "HEALTHCHECK_PATH")]
+    path: String,
+
+    // HOW LONG THE WHOLE CHECK (INCLUDING CONNECTING) MAY TAKE
+    #[arg(long, default_value_t = 3000, env = "HEALTHCHECK_TIMEOUT_MS")]
+    timeout_ms: u64,
+
+    // ALSO GENERATE AN ID AND CHECK THAT IT DECODES TO THE EXPECTED WORKER ID
+    #[arg(long, env = "HEALTHCHECK_DEEP")]
+    deep: bool,
+
+    // THE WORKER ID THAT --deep EXPECTS. IF THIS ISN'T A NUMBER (I.E. "FROM_HOSTNAME"), THE WORKER ID
+    // REPORTED BY /info IS USED INSTEAD
+    #[arg(long, env = "WORKER_ID")]
+    worker_id: Option<String>,
+}
+
+/// Why the worker is considered unhealthy.
+#[derive(Debug)]
+enum Failure {
+    /// The worker couldn't be reached at all
+    Connect(io::Error),
+    /// The worker didn't answer within `HEALTHCHECK_TIMEOUT_MS`
+    Timeout,
+    /// The worker answered with something other than `200 OK`
+    BadStatus {
+        path: String,
+        status: u16,
+        body: String,
+    },
+    /// The worker's answer couldn't be understood, or `--deep` got back an unexpected ID
+    InvalidResponse(String),
+}
+
+impl Failure {
+    // NOTE(ayubun): 2 is left out, since that's what clap exits with for invalid args (and docker
+    // reserves it for healthchecks anyway)
+    fn exit_code(&self) -> u8 {
+        match self {
+            Failure::BadStatus { .. } => 1,
+            Failure::Connect(_) => 3,
+            Failure::Timeout => 4,
+            Failure::InvalidResponse(_) => 5,
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Connect(err) => write!(f, "could not connect to the worker: {err}"),
+            Failure::Timeout => write!(f, "timed out waiting for the worker"),
+            Failure::BadStatus { path, status, body } => {
+                write!(f, "{path} responded with {status}: {body}")
+            }
+            Failure::InvalidResponse(message) => write!(f, "invalid response: {message}"),
+        }
+    }
+}
+
+fn io_failure(err: io::Error) -> Failure {
+    match err.kind() {
+        // NOTE(ayubun): read timeouts show up as `WouldBlock` on unix and `TimedOut` on windows
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => Failure::Timeout,
+        _ => Failure::Connect(err),
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Response {
+    status: u16,
+    body: Vec<u8>,
+}
+
+/// Sends one request per connection, all of them sharing a single deadline.
+struct Client {
+    host: String,
+    port: u16,
+    deadline: Instant,
+}
+
+impl Client {
+    fn remaining(&self) -> Result<Duration, Failure> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(Failure::Timeout);
+        }
+        Ok(remaining)
+    }
+
+    fn connect(&self) -> Result<TcpStream, Failure> {
+        let addrs = (self.host.as_str(), self.port)
+            .to_socket_addrs()
+            .map_err(Failure::Connect)?;
+        let mut last_err = io::Error::new(io::ErrorKind::NotFound, "host has no addresses");
+        for addr in addrs {
+            match TcpStream::connect_timeout(&addr, self.remaining()?) {
+                Ok(stream) => return Ok(stream),
+                Err(err) if err.kind() == io::ErrorKind::TimedOut => return Err(Failure::Timeout),
+                Err(err) => last_err = err,
+            }
+        }
+        Err(Failure::Connect(last_err))
+    }
+
+    fn request(&self, method: &str, path: &str, body: Option<&str>) -> Result<Response, Failure> {
+        let mut stream = self.connect()?;
+
+        // NOTE(ayubun): `Connection: close` lets us read until EOF instead of having to track
+        // where the response ends
+        let host = if self.host.contains(':') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        };
+        let mut request = format!(
+            "{method} {path} HTTP/1.1\r\n\
+             Host: {host}:{port}\r\n\
+             User-Agent: snowflake-id-worker-healthcheck\r\n\
+             Accept: application/json\r\n\
+             Connection: close\r\n",
+            port = self.port,
+        );
+        if let Some(body) = body {
+            request.push_str(&format!(
+                "Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+                body.len()
+            ));
+        } else {
+            request.push_str("\r\n");
+        }
+        stream
+            .set_write_timeout(Some(self.remaining()?))
+            .map_err(io_failure)?;
+        stream.write_all(request.as_bytes()).map_err(io_failure)?;
+
+        let mut raw = Vec::new();
+        let mut buffer = [0; 4096];
+        loop {
+            stream
+                .set_read_timeout(Some(self.remaining()?))
+                .map_err(io_failure)?;
+            match stream.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => raw.extend_from_slice(&buffer[..read]),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(io_failure(err)),
+            }
+        }
+        parse_response(&raw).map_err(Failure::InvalidResponse)
+    }
+
+    /// Sends a request that must succeed with `200 OK`, returning its body.
+    fn expect_ok(&self, method: &str, path: &str, body: Option<&str>) -> Result<Vec<u8>, Failure> {
+        let response = self.request(method, path, body)?;
+        if response.status != 200 {
+            return Err(Failure::BadStatus {
+                path: path.to_string(),
+                status: response.status,
+                body: String::from_utf8_lossy(&response.body).trim().to_string(),
+            });
+        }
+        Ok(response.body)
+    }
+
+    fn expect_json<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<&str>,
+    ) -> Result<T, Failure> {
+        let body = self.expect_ok(method, path, body)?;
+        serde_json::from_slice(&body)
+            .map_err(|err| Failure::InvalidResponse(format!("{path} returned bad JSON: {err}")))
+    }
+}
+
+/// Parses a complete `HTTP/1.x` response, decoding the body if it was sent in chunks.
+fn parse_response(raw: &[u8]) -> Result<Response, String> {
+    let header_end = raw
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or("response ended before its headers did")?;
+    let head = std::str::from_utf8(&raw[..header_end]).map_err(|_| "headers aren't UTF-8")?;
+    let body = &raw[header_end + 4..];
+
+    let mut lines = head.split("\r\n");
+    let status_line = lines.next().unwrap_or_default();
+    let mut parts = status_line.splitn(3, ' ');
+    let version = parts.next().unwrap_or_default();
+    if !version.starts_with("HTTP/1.") {
+        return Err(format!("unexpected status line \"{status_line}\""));
+    }
+    let status = parts
+        .next()
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(|| format!("unexpected status line \"{status_line}\""))?;
+
+    let mut chunked = false;
+    let mut content_length = None;
+    for line in lines {
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| format!("malformed header \"{line}\""))?;
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("transfer-encoding") {
+            chunked = value.eq_ignore_ascii_case("chunked");
+        } else if name.eq_ignore_ascii_case("content-length") {
+            content_length = Some(
+                value
+                    .parse::<usize>()
+                    .map_err(|_| format!("invalid content-length \"{value}\""))?,
+            );
+        }
+    }
+
+    let body = if chunked {
+        decode_chunked(body)?
+    } else if let Some(length) = content_length {
+        body.get(..length)
+            .ok_or("response ended before its body did")?
+            .to_vec()
+    } else {
+        body.to_vec()
+    };
+    Ok(Response { status, body })
+}
+
+fn decode_chunked(mut raw: &[u8]) -> Result<Vec<u8>, String> {
+    let mut body = Vec::new();
+    loop {
+        let line_end = raw
+            .windows(2)
+            .position(|window| window == b"\r\n")
+            .ok_or("response ended before its last chunk")?;
+        let size = std::str::from_utf8(&raw[..line_end])
+            .ok()
+            .map(|line| line.split(';').next().unwrap_or_default().trim())
+            .and_then(|size| usize::from_str_radix(size, 16).ok())
+            .ok_or("invalid chunk size")?;
+        raw = &raw[line_end + 2..];
+        if size == 0 {
+            return Ok(body);
+        }
+        body.extend_from_slice(raw.get(..size).ok_or("response ended mid-chunk")?);
+        raw = raw.get(size + 2..).ok_or("response ended mid-chunk")?;
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct Info {
+    worker_id: u64,
+}
+
+#[derive(serde::Deserialize)]
+struct DecodedId {
+    id: i64,
+    worker_id: u64,
+}
+
+/// Generates a single ID and makes sure that the worker can decode it back to the worker ID we
+/// expect it to have.
+fn deep_check(client: &Client, expected_worker_id: Option<u64>) -> Result<(), Failure> {
+    let ids: Vec<i64> = client.expect_json("POST", "/generate", Some(r#"{"count":1}"#))?;
+    let [id] = ids[..] else {
+        return Err(Failure::InvalidResponse(format!(
+            "/generate returned {} IDs instead of 1",
+            ids.len()
+        )));
+    };
+
+    let decoded: Vec<DecodedId> =
+        client.expect_json("POST", "/decode", Some(&format!(r#"{{"ids":[{id}]}}"#)))?;
+    let decoded = match &decoded[..] {
+        [decoded] if decoded.id == id => decoded,
+        _ => {
+            return Err(Failure::InvalidResponse(format!(
+                "/decode did not decode {id}"
+            )))
+        }
+    };
+
+    let expected_worker_id = match expected_worker_id {
+        Some(worker_id) => worker_id,
+        None => client.expect_json::<Info>("GET", "/info", None)?.worker_id,
+    };
+    if decoded.worker_id != expected_worker_id {
+        return Err(Failure::InvalidResponse(format!(
+            "{id} has worker ID {}, expected {expected_worker_id}",
+            decoded.worker_id
+        )));
+    }
+    Ok(())
+}
+
+fn check(args: &Args) -> Result<(), Failure> {
+    let client = Client {
+        host: args.host.clone(),
+        port: args.port,
+        deadline: Instant::now() + Duration::from_millis(args.timeout_ms),
+    };
+    client.expect_ok("GET", &args.path, None)?;
+    if args.deep {
+        let expected_worker_id = args
+            .worker_id
+            .as_deref()
+            .and_then(|worker_id| worker_id.parse().ok());
+        deep_check(&client, expected_worker_id)?;
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    match check(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(failure) => {
+            eprintln!("unhealthy: {failure}");
+            ExitCode::from(failure.exit_code())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use snowflake_id_worker::{create_routes_with, WorkerConfig};
+    use std::net::TcpListener;
+
+    fn args(port: u16) -> Args {
+        Args {
+            host: "127.0.0.1".to_string(),
+            port,
+            path: "/health".to_string(),
+            timeout_ms: 2000,
+            deep: false,
+            worker_id: None,
+        }
+    }
+
+    /// Serves a worker with the given ID on a random port, returning the port.
+    fn serve_worker(worker_id: u64) -> u16 {
+        let config = WorkerConfig::builder()
+            .worker_id(worker_id)
+            .build()
+            .unwrap();
+        let routes = create_routes_with(config);
+        let (addr_tx, addr_rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            tokio::runtime::Runtime::new().unwrap().block_on(async {
+                let (addr, server) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+                addr_tx.send(addr).unwrap();
+                server.await;
+            })
+        });
+        addr_rx.recv().unwrap().port()
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let raw = b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nOK";
+        assert_eq!(
+            parse_response(raw),
+            Ok(Response {
+                status: 200,
+                body: b"OK".to_vec()
+            })
+        );
+
+        let raw = b"HTTP/1.1 503 Service Unavailable\r\nTransfer-Encoding: chunked\r\n\r\n\
+                    3\r\n[1,\r\n2;ext=1\r\n2]\r\n0\r\n\r\n";
+        assert_eq!(
+            parse_response(raw),
+            Ok(Response {
+                status: 503,
+                body: b"[1,2]".to_vec()
+            })
+        );
+
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\n").is_err());
+        assert!(parse_response(b"SSH-2.0-OpenSSH\r\n\r\n").is_err());
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\nOK").is_err());
+        assert!(
+            parse_response(b"HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n5\r\nOK")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_check_healthy_worker() {
+        let port = serve_worker(7);
+        assert!(check(&args(port)).is_ok());
+
+        let deep = Args {
+            deep: true,
+            ..args(port)
+        };
+        assert!(check(&deep).is_ok(), "Should fall back to /info");
+        let deep = Args {
+            worker_id: Some("7".to_string()),
+            ..deep
+        };
+        assert!(check(&deep).is_ok());
 
-    let mut stream = TcpStream::connect(("127.0.0.1", port)).expect("tcp connect");
+        let wrong_worker_id = Args {
+            worker_id: Some("8".to_string()),
+            ..deep
+        };
+        let failure = check(&wrong_worker_id).unwrap_err();
+        assert_eq!(failure.exit_code(), 5, "{failure}");
+    }
 
-    let data = "GET /health HTTP/1.1\r\n\r\n";
-    stream.write_all(data.as_bytes()).expect("write data");
+    #[test]
+    fn test_check_failures() {
+        let port = serve_worker(0);
+        let not_found = Args {
+            path: "/nope".to_string(),
+            ..args(port)
+        };
+        let failure = check(&not_found).unwrap_err();
+        assert!(matches!(failure, Failure::BadStatus { status: 404, .. }));
+        assert_eq!(failure.exit_code(), 1);
 
-    let mut buffer = [0; 12];
-    stream.read_exact(&mut buffer).expect("read data");
+        // NOTE(ayubun): nothing is listening once the listener is dropped
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let failure = check(&args(port)).unwrap_err();
+        assert_eq!(failure.exit_code(), 3, "{failure}");
 
-    assert!(buffer == "HTTP/1.1 200".as_bytes(), "response is not ok");
+        // NOTE(ayubun): this accepts connections (via the backlog) but never answers them
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let silent = Args {
+            timeout_ms: 200,
+            ..args(listener.local_addr().unwrap().port())
+        };
+        let started = Instant::now();
+        let failure = check(&silent).unwrap_err();
+        assert_eq!(failure.exit_code(), 4, "{failure}");
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
 }
@@ -0,0 +1,126 @@
+use std::{
+    fmt,
+    sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering},
+    time::{Duration, SystemTime},
+};
+
+use crate::generator::millis_since;
+
+/// What a generator should do when the system clock reads earlier than the timestamp of the last
+/// ID it issued (i.e. because NTP stepped the wall clock backwards).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ClockRegressionPolicy {
+    /// Block until the clock catches up, failing if that would take longer than the max wait
+    Wait,
+    /// Keep issuing IDs with the last timestamp until the clock catches up
+    Hold,
+    /// Fail immediately
+    Fail,
+}
+
+/// Returned when a generator refuses to issue an ID because the clock moved backwards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockRegressionError {
+    pub drift_ms: i64,
+}
+
+impl fmt::Display for ClockRegressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Clock moved backwards by {}ms: refusing to generate IDs until it catches up",
+            self.drift_ms
+        )
+    }
+}
+
+impl std::error::Error for ClockRegressionError {}
+
+/// Keeps track of clock regressions seen by a generator, so that they can be surfaced outside of
+/// the generator (i.e. by the health endpoint) without taking its lock.
+pub(crate) struct ClockMonitor {
+    epoch: SystemTime,
+    last_timestamp_ms: AtomicI64,
+    regressed: AtomicBool,
+    events: AtomicU64,
+    last_drift_ms: AtomicI64,
+}
+
+impl ClockMonitor {
+    pub(crate) fn new(epoch: SystemTime) -> ClockMonitor {
+        ClockMonitor {
+            epoch,
+            last_timestamp_ms: AtomicI64::new(-1),
+            regressed: AtomicBool::new(false),
+            events: AtomicU64::new(0),
+            last_drift_ms: AtomicI64::new(0),
+        }
+    }
+
+    /// Records the timestamp of the most recently issued ID.
+    #[inline(always)]
+    pub(crate) fn record_issued(&self, timestamp_ms: i64) {
+        // NOTE(ayubun): the atomic generator can record timestamps from several threads at once,
+        // so this has to make sure that a late store never moves the mark backwards
+        self.last_timestamp_ms
+            .fetch_max(timestamp_ms, Ordering::Relaxed);
+    }
+
+    /// Records that the clock was seen `drift_ms` behind the last issued ID. Only the first
+    /// observation of each regression counts as a new event, in which case this returns `true`.
+    pub(crate) fn record_regression(&self, drift_ms: i64, policy: ClockRegressionPolicy) -> bool {
+        self.last_drift_ms.store(drift_ms, Ordering::Relaxed);
+        if self.regressed.swap(true, Ordering::Relaxed) {
+            return false;
+        }
+        let events = self.events.fetch_add(1, Ordering::Relaxed) + 1;
+        tracing::warn!(
+            drift_ms,
+            events,
+            clock_regression_policy = ?policy,
+            "clock moved backwards"
+        );
+        true
+    }
+
+    /// Records that the clock has caught up with the last issued ID again.
+    #[inline(always)]
+    pub(crate) fn record_caught_up(&self) {
+        if self.regressed.load(Ordering::Relaxed) && self.regressed.swap(false, Ordering::Relaxed) {
+            tracing::info!(
+                drift_ms = self.last_drift_ms.load(Ordering::Relaxed),
+                "clock caught up after moving backwards"
+            );
+        }
+    }
+
+    /// The timestamp of the most recently issued ID, or `-1` if no IDs have been issued yet.
+    pub(crate) fn last_issued_ms(&self) -> i64 {
+        self.last_timestamp_ms.load(Ordering::Relaxed)
+    }
+
+    /// The total number of clock regressions seen since startup.
+    pub(crate) fn events(&self) -> u64 {
+        self.events.load(Ordering::Relaxed)
+    }
+
+    /// Returns how far (in milliseconds) the clock is currently behind the last issued ID, if
+    /// it is behind at all.
+    pub(crate) fn current_drift_ms(&self) -> Option<i64> {
+        let drift_ms = self.last_timestamp_ms.load(Ordering::Relaxed) - millis_since(self.epoch);
+        (drift_ms > 0).then_some(drift_ms)
+    }
+}
+
+/// Blocks the current thread until the clock reaches `timestamp_ms`. This can take up to
+/// `CLOCK_REGRESSION_MAX_WAIT`, so it must never run on an async runtime's worker threads (see
+/// [`Worker::generate_batch_async`](crate::Worker::generate_batch_async)).
+pub(crate) fn wait_until(timestamp_ms: i64, epoch: SystemTime) -> i64 {
+    loop {
+        let now_ms = millis_since(epoch);
+        if now_ms >= timestamp_ms {
+            return now_ms;
+        }
+        std::thread::sleep(Duration::from_millis((timestamp_ms - now_ms) as u64));
+    }
+}
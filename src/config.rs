@@ -0,0 +1,780 @@
+use clap::Parser;
+use regex::Regex;
+use std::{
+    env,
+    net::{IpAddr, Ipv4Addr, SocketAddrV4},
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    clock::ClockRegressionPolicy,
+    generator::{BitLayout, GeneratorKind, DEFAULT_LAYOUT},
+    logging::{self, LogFormat, DEFAULT_LOG_LEVEL},
+    state::HighWaterMarkPolicy,
+    ConfigError,
+};
+
+const DEFAULT_PORT: u16 = 8080;
+const DEFAULT_EPOCH: SystemTime = UNIX_EPOCH;
+const DEFAULT_CLOCK_REGRESSION_MAX_WAIT: Duration = Duration::from_millis(1000);
+const DEFAULT_STATE_FILE_INTERVAL: Duration = Duration::from_millis(1000);
+const DEFAULT_SHUTDOWN_DRAIN: Duration = Duration::from_millis(5000);
+const DEFAULT_LEASE_TTL: Duration = Duration::from_millis(10_000);
+const DEFAULT_LEASE_KEY_PREFIX: &str = "snowflake-id-worker";
+const DEFAULT_GOSSIP_INTERVAL: Duration = Duration::from_millis(1000);
+// NOTE(ayubun): the same as sonyflake, which uses the lower 16 bits of the IP as its machine ID
+const DEFAULT_IP_PREFIX_LEN: u8 = 16;
+// NOTE(ayubun): 100k IDs is ~25ms worth of sequence numbers with the default layout, and only
+// ~800KB of memory, so a single request can't starve everyone else for long
+const DEFAULT_MAX_BATCH_SIZE: u64 = 100_000;
+// NOTE(ayubun): streamed IDs aren't held in memory, but they still use up the sequence. 10M IDs is
+// ~2.5s worth of it with the default layout, which is plenty for a bulk backfill
+const DEFAULT_MAX_STREAM_SIZE: u64 = 10_000_000;
+
+#[derive(Debug, clap::Parser)]
+struct Args {
+    #[arg(long, default_value_t = DEFAULT_PORT, env = "PORT")]
+    port: u16,
+
+    // THE PORT TO SERVE THE GRPC API ON. GRPC IS DISABLED IF THIS ISN'T SET
+    #[arg(long, env = "GRPC_PORT")]
+    grpc_port: Option<u16>,
+
+    // TO SET WORKER ID AUTOMATICALLY IN A K8S STATEFUL SET, SET TO "FROM_HOSTNAME". TO CLAIM A FREE
+    // ONE FROM LEASE_STORE_URL, SET TO "FROM_LEASE", OR FROM LOCKFILE_DIR, SET TO "FROM_LOCKFILE". TO
+    // DERIVE ONE FROM THE LOW BITS OF THE POD'S IP, SET TO "FROM_IP"
+    #[arg(long, default_value = "0", env = "WORKER_ID")]
+    worker_id: String,
+
+    // A REGEX THAT CAPTURES THE WORKER ID FROM THE HOSTNAME (IN A GROUP NAMED "id", OR ITS FIRST GROUP),
+    // I.E. "^worker-(\d+)\.". BY DEFAULT, IT'S WHATEVER FOLLOWS THE LAST "-" BEFORE THE FIRST "."
+    #[arg(long, env = "WORKER_ID_HOSTNAME_PATTERN")]
+    worker_id_hostname_pattern: Option<String>,
+
+    // ADDED TO THE WORKER ID PARSED FROM THE HOSTNAME, I.E. TO GIVE EACH STATEFUL SET ITS OWN RANGE
+    #[arg(
+        long,
+        default_value_t = 0,
+        env = "WORKER_ID_OFFSET",
+        allow_negative_numbers = true
+    )]
+    worker_id_offset: i64,
+
+    // SET TO "FROM_LEASE" OR "FROM_IP" (ALONG WITH WORKER_ID) TO CLAIM OR DERIVE THE DATA CENTER ID TOO,
+    // OR TO "FROM_HOSTNAME" TO PARSE IT WITH DATA_CENTER_ID_HOSTNAME_PATTERN
+    #[arg(long, default_value = "0", env = "DATA_CENTER_ID")]
+    data_center_id: String,
+
+    // LIKE WORKER_ID_HOSTNAME_PATTERN, BUT FOR DATA_CENTER_ID=FROM_HOSTNAME (WHICH REQUIRES IT)
+    #[arg(long, env = "DATA_CENTER_ID_HOSTNAME_PATTERN")]
+    data_center_id_hostname_pattern: Option<String>,
+
+    // THE IP THAT WORKER_ID=FROM_IP DERIVES THE WORKER ID FROM (I.E. THE POD'S status.podIP). BY
+    // DEFAULT, IT'S THE FIRST PRIVATE IPV4 ADDRESS OF A LOCAL INTERFACE
+    #[arg(long, env = "POD_IP")]
+    pod_ip: Option<Ipv4Addr>,
+
+    // THE PREFIX LENGTH OF THE SUBNET THAT EVERY WORKER'S IP IS IN, I.E. 22 FOR A /22.
+    // WORKER_ID=FROM_IP USES THE BITS AFTER THE PREFIX
+    #[arg(long, default_value_t = DEFAULT_IP_PREFIX_LEN, env = "IP_PREFIX_LEN")]
+    ip_prefix_len: u8,
+
+    #[arg(long, env = "EPOCH")]
+    epoch: Option<u64>,
+
+    // THE WIDTH OF EACH FIELD IN AN ID. THESE MUST ADD UP TO 63 BITS
+    #[arg(long, default_value_t = DEFAULT_LAYOUT.timestamp_bits, env = "TIMESTAMP_BITS")]
+    timestamp_bits: u8,
+
+    #[arg(long, default_value_t = DEFAULT_LAYOUT.data_center_id_bits, env = "DATA_CENTER_ID_BITS")]
+    data_center_id_bits: u8,
+
+    #[arg(long, default_value_t = DEFAULT_LAYOUT.worker_id_bits, env = "WORKER_ID_BITS")]
+    worker_id_bits: u8,
+
+    #[arg(long, default_value_t = DEFAULT_LAYOUT.sequence_bits, env = "SEQUENCE_BITS")]
+    sequence_bits: u8,
+
+    // WHAT TO DO WHEN THE CLOCK MOVES BACKWARDS: "wait", "hold" OR "fail"
+    #[arg(long, value_enum, default_value_t = ClockRegressionPolicy::Wait, env = "CLOCK_REGRESSION_POLICY")]
+    clock_regression_policy: ClockRegressionPolicy,
+
+    #[arg(long, default_value_t = DEFAULT_CLOCK_REGRESSION_MAX_WAIT.as_millis() as u64, env = "CLOCK_REGRESSION_MAX_WAIT_MS")]
+    clock_regression_max_wait_ms: u64,
+
+    // AN OPTIONAL FILE TO PERSIST THE HIGH-WATER MARK TIMESTAMP TO, SO RESTARTS CAN NEVER REISSUE IDS
+    #[arg(long, env = "STATE_FILE")]
+    state_file: Option<PathBuf>,
+
+    #[arg(long, default_value_t = DEFAULT_STATE_FILE_INTERVAL.as_millis() as u64, env = "STATE_FILE_INTERVAL_MS")]
+    state_file_interval_ms: u64,
+
+    // WHAT TO DO WHEN THE CLOCK IS BEHIND THE HIGH-WATER MARK ON STARTUP: "wait" OR "fail"
+    #[arg(long, value_enum, default_value_t = HighWaterMarkPolicy::Wait, env = "HIGH_WATER_MARK_POLICY")]
+    high_water_mark_policy: HighWaterMarkPolicy,
+
+    // WHICH GENERATOR TO USE: "atomic" (LOCK-FREE) OR "mutex"
+    #[arg(long, value_enum, default_value_t = GeneratorKind::Atomic, env = "GENERATOR")]
+    generator: GeneratorKind,
+
+    // THE LARGEST `count` THAT A SINGLE REQUEST CAN ASK FOR
+    #[arg(long, default_value_t = DEFAULT_MAX_BATCH_SIZE, env = "MAX_BATCH_SIZE")]
+    max_batch_size: u64,
+
+    // THE LARGEST `count` THAT A SINGLE STREAMED REQUEST (OR gRPC `GenerateStream`) CAN ASK FOR
+    #[arg(long, default_value_t = DEFAULT_MAX_STREAM_SIZE, env = "MAX_STREAM_SIZE")]
+    max_stream_size: u64,
+
+    // ALSO SERVE `GET /generate?count=N` FOR CLIENTS THAT CAN ONLY SEND GETS
+    #[arg(long, env = "ALLOW_GET_GENERATE")]
+    allow_get_generate: bool,
+
+    // HOW LONG TO KEEP SERVING (WITH /health FAILING) AFTER SIGTERM, SO LOAD BALANCERS CAN STOP SENDING REQUESTS
+    #[arg(long, default_value_t = DEFAULT_SHUTDOWN_DRAIN.as_millis() as u64, env = "SHUTDOWN_DRAIN_MS")]
+    shutdown_drain_ms: u64,
+
+    // THE REDIS-COMPATIBLE STORE THAT WORKER_ID=FROM_LEASE CLAIMS WORKER IDS FROM, I.E. "redis://redis:6379"
+    #[arg(long, env = "LEASE_STORE_URL")]
+    lease_store_url: Option<String>,
+
+    #[arg(long, default_value = DEFAULT_LEASE_KEY_PREFIX, env = "LEASE_KEY_PREFIX")]
+    lease_key_prefix: String,
+
+    // HOW LONG A WORKER ID LEASE LASTS WITHOUT BEING RENEWED. IT'S RENEWED EVERY THIRD OF THIS
+    #[arg(long, default_value_t = DEFAULT_LEASE_TTL.as_millis() as u64, env = "LEASE_TTL_MS")]
+    lease_ttl_ms: u64,
+
+    // A DIRECTORY SHARED BY EVERY WORKER ON THE HOST, THAT WORKER_ID=FROM_LOCKFILE LOCKS A FREE SLOT IN
+    #[arg(long, env = "LOCKFILE_DIR")]
+    lockfile_dir: Option<PathBuf>,
+
+    // A MULTICAST GROUP (I.E. "239.255.42.99:7474") TO ANNOUNCE THIS WORKER'S IDENTITY ON, AND TO WATCH
+    // FOR OTHER WORKERS WITH THE SAME ONE. GOSSIP IS DISABLED IF THIS ISN'T SET
+    #[arg(long, env = "GOSSIP_GROUP")]
+    gossip_group: Option<SocketAddrV4>,
+
+    // THE LOCAL INTERFACE TO GOSSIP ON. BY DEFAULT, THE OS PICKS ONE
+    #[arg(long, default_value_t = Ipv4Addr::UNSPECIFIED, env = "GOSSIP_INTERFACE")]
+    gossip_interface: Ipv4Addr,
+
+    #[arg(long, default_value_t = DEFAULT_GOSSIP_INTERVAL.as_millis() as u64, env = "GOSSIP_INTERVAL_MS")]
+    gossip_interval_ms: u64,
+
+    // HOW LOGS ARE WRITTEN: "pretty" OR "json"
+    #[arg(long, value_enum, default_value_t = LogFormat::Pretty, env = "LOG_FORMAT")]
+    log_format: LogFormat,
+
+    // EITHER A LEVEL (I.E. "debug") OR A LIST OF DIRECTIVES (I.E. "warn,snowflake_id_worker=debug")
+    #[arg(long, default_value = DEFAULT_LOG_LEVEL, env = "LOG_LEVEL")]
+    log_level: String,
+}
+
+enum HostnameIdError {
+    /// The pattern didn't match, or the hostname has no `-`
+    NotFound,
+    NotANumber,
+}
+
+fn compile_hostname_pattern(variable: &'static str, pattern: &str) -> Result<Regex, ConfigError> {
+    let invalid = |message: String| ConfigError::InvalidHostnamePattern { variable, message };
+    let regex = Regex::new(pattern).map_err(|err| invalid(err.to_string()))?;
+    if regex.captures_len() < 2 {
+        return Err(invalid("must capture the ID in a group".to_string()));
+    }
+    Ok(regex)
+}
+
+/// Extracts an ID from `hostname`, either from the `id` group (or first group) of `pattern`, or
+/// from whatever follows the last `-` of the hostname without its domain, i.e. `3` for both
+/// `worker-3` and `worker-3.svc.cluster.local`.
+fn id_from_hostname(hostname: &str, pattern: Option<&Regex>) -> Result<u64, HostnameIdError> {
+    let id = match pattern {
+        Some(pattern) => pattern
+            .captures(hostname)
+            .and_then(|captures| captures.name("id").or_else(|| captures.get(1)))
+            .ok_or(HostnameIdError::NotFound)?
+            .as_str(),
+        None => {
+            let short_hostname = hostname.split('.').next().unwrap_or_default();
+            short_hostname
+                .rsplit_once('-')
+                .ok_or(HostnameIdError::NotFound)?
+                .1
+        }
+    };
+    id.parse::<u64>().map_err(|_| HostnameIdError::NotANumber)
+}
+
+/// The first private IPv4 address of a local interface.
+fn private_ipv4() -> Option<Ipv4Addr> {
+    // NOTE(ayubun): like HOSTNAME_FOR_TESTING, this lets tests pretend to have other interfaces. it's
+    // a comma-separated list of their addresses
+    let ips: Vec<IpAddr> = match env::var("IP_FOR_TESTING") {
+        Ok(ips) => ips
+            .split(',')
+            .filter_map(|ip| ip.trim().parse().ok())
+            .collect(),
+        Err(_) => if_addrs::get_if_addrs()
+            .map(|interfaces| interfaces.iter().map(if_addrs::Interface::ip).collect())
+            .unwrap_or_default(),
+    };
+    ips.into_iter().find_map(|ip| match ip {
+        IpAddr::V4(ip) if ip.is_private() => Some(ip),
+        _ => None,
+    })
+}
+
+/// Derives a worker ID from the bits of `ip` after its first `prefix_len` bits, and if
+/// `data_center_id` is set, a data center ID from the bits above the worker ID bits.
+fn ids_from_ip(
+    ip: Ipv4Addr,
+    prefix_len: u8,
+    layout: BitLayout,
+    data_center_id: bool,
+) -> Result<(u64, Option<u64>), ConfigError> {
+    if prefix_len > 32 {
+        return Err(ConfigError::InvalidIpPrefixLen { prefix_len });
+    }
+    // NOTE(ayubun): the layout is validated again in `build`, but the shift below needs it to make
+    // sense before then
+    layout.validate().map_err(ConfigError::InvalidLayout)?;
+
+    let host = u64::from(u32::from(ip) & u32::MAX.checked_shr(prefix_len.into()).unwrap_or(0));
+    let max = if data_center_id {
+        (layout.max_data_center_id() << layout.worker_id_bits) | layout.max_worker_id()
+    } else {
+        layout.max_worker_id()
+    };
+    // NOTE(ayubun): this is never truncated to fit, since two IPs in the subnet could then end up
+    // with the same worker ID
+    if host > max {
+        return Err(ConfigError::IpOutOfRange {
+            ip,
+            prefix_len,
+            host,
+            max,
+        });
+    }
+    Ok((
+        host & layout.max_worker_id(),
+        data_center_id.then(|| host >> layout.worker_id_bits),
+    ))
+}
+
+/// The validated configuration of a worker.
+///
+/// This can either be built explicitly with [`WorkerConfig::builder`], or parsed from CLI args
+/// and environment variables with [`WorkerConfig::try_from_env`].
+#[derive(Debug, Clone)]
+pub struct WorkerConfig {
+    pub(crate) port: u16,
+    pub(crate) grpc_port: Option<u16>,
+    pub(crate) worker_id: u64,
+    pub(crate) data_center_id: u64,
+    pub(crate) epoch: SystemTime,
+    pub(crate) layout: BitLayout,
+    pub(crate) clock_regression_policy: ClockRegressionPolicy,
+    pub(crate) clock_regression_max_wait: Duration,
+    pub(crate) state_file: Option<PathBuf>,
+    pub(crate) state_file_interval: Duration,
+    pub(crate) high_water_mark_policy: HighWaterMarkPolicy,
+    pub(crate) generator_kind: GeneratorKind,
+    pub(crate) max_batch_size: u64,
+    pub(crate) max_stream_size: u64,
+    pub(crate) allow_get_generate: bool,
+    pub(crate) shutdown_drain: Duration,
+    pub(crate) lease_store: Option<String>,
+    pub(crate) lease_key_prefix: String,
+    pub(crate) lease_ttl: Duration,
+    pub(crate) lease_data_center_id: bool,
+    pub(crate) lockfile_dir: Option<PathBuf>,
+    pub(crate) gossip_group: Option<SocketAddrV4>,
+    pub(crate) gossip_interface: Ipv4Addr,
+    pub(crate) gossip_interval: Duration,
+    pub(crate) log_format: LogFormat,
+    pub(crate) log_level: String,
+}
+
+impl WorkerConfig {
+    /// Returns a builder with the same defaults as the `snowflake-id-worker` binary.
+    pub fn builder() -> WorkerConfigBuilder {
+        WorkerConfigBuilder::default()
+    }
+
+    /// Parses the worker config from CLI args and environment variables, returning a
+    /// [`ConfigError`] instead of panicking if anything is invalid.
+    pub fn try_from_env() -> Result<WorkerConfig, ConfigError> {
+        let args = if cfg!(test) {
+            // NOTE(ayubun): during tests, we should only parse from environment variables.
+            // CLI args will conflict with the necessary `--test-threads=1` flag, which
+            // is needed to run tests in series so that the environment variables don't conflict
+            Args::try_parse_from([""])
+        } else {
+            Args::try_parse()
+        }
+        .map_err(ConfigError::InvalidArgs)?;
+
+        // NOTE(ayubun): for testing, i'm allowing hostname to be set via an environment variable.
+        // this is so we can ensure the hostname parsing works as expected~
+        let hostname = env::var("HOSTNAME_FOR_TESTING").unwrap_or_else(|_| {
+            hostname::get()
+                .map(|os| os.to_string_lossy().into_owned())
+                .unwrap_or_else(|_| "localhost".to_string())
+        });
+
+        let lease = args.worker_id.eq_ignore_ascii_case("FROM_LEASE");
+        let lease_data_center_id = args.data_center_id.eq_ignore_ascii_case("FROM_LEASE");
+        let lease_store = match (lease, args.lease_store_url) {
+            (true, Some(url)) => Some(url),
+            (true, None) => return Err(ConfigError::MissingLeaseStore),
+            (false, _) => None,
+        };
+        let lockfile = args.worker_id.eq_ignore_ascii_case("FROM_LOCKFILE");
+        let lockfile_dir = match (lockfile, args.lockfile_dir) {
+            (true, Some(dir)) => Some(dir),
+            (true, None) => return Err(ConfigError::MissingLockfileDir),
+            (false, _) => None,
+        };
+
+        let layout = BitLayout {
+            timestamp_bits: args.timestamp_bits,
+            data_center_id_bits: args.data_center_id_bits,
+            worker_id_bits: args.worker_id_bits,
+            sequence_bits: args.sequence_bits,
+        };
+        let from_ip = args.worker_id.eq_ignore_ascii_case("FROM_IP");
+        let ip_data_center_id = from_ip && args.data_center_id.eq_ignore_ascii_case("FROM_IP");
+        let ip_ids = if from_ip {
+            let ip = match args.pod_ip {
+                Some(ip) => ip,
+                None => private_ipv4().ok_or(ConfigError::NoPrivateIp)?,
+            };
+            Some(ids_from_ip(
+                ip,
+                args.ip_prefix_len,
+                layout,
+                ip_data_center_id,
+            )?)
+        } else {
+            None
+        };
+
+        let worker_id = if lease || lockfile {
+            // NOTE(ayubun): this is only a placeholder until the worker ID is claimed on startup
+            0
+        } else if args.worker_id.eq_ignore_ascii_case("FROM_HOSTNAME") {
+            // NOTE(ayubun): assuming this is being run from a stateful set in k8s:
+            //
+            // snowflake-id-worker-0
+            // snowflake-id-worker-1
+            // ...
+            // snowflake-id-worker-n
+            //
+            // this code will try to grab the pod's index (n) and use it as the worker id
+            let pattern = args
+                .worker_id_hostname_pattern
+                .as_deref()
+                .map(|pattern| compile_hostname_pattern("WORKER_ID_HOSTNAME_PATTERN", pattern))
+                .transpose()?;
+            let ordinal = match id_from_hostname(&hostname, pattern.as_ref()) {
+                Ok(ordinal) => ordinal,
+                Err(HostnameIdError::NotFound) => {
+                    return Err(ConfigError::HostnameWithoutWorkerId { hostname })
+                }
+                Err(HostnameIdError::NotANumber) => {
+                    return Err(ConfigError::InvalidHostnameWorkerId { hostname })
+                }
+            };
+            ordinal.checked_add_signed(args.worker_id_offset).ok_or(
+                ConfigError::WorkerIdOffsetOutOfRange {
+                    ordinal,
+                    offset: args.worker_id_offset,
+                },
+            )?
+        } else if let Some((worker_id, _)) = ip_ids {
+            worker_id
+        } else {
+            args.worker_id
+                .parse::<u64>()
+                .map_err(|_| ConfigError::InvalidWorkerId {
+                    value: args.worker_id.clone(),
+                })?
+        };
+
+        let data_center_id = if lease && lease_data_center_id {
+            0
+        } else if let Some((_, Some(data_center_id))) = ip_ids {
+            data_center_id
+        } else if args.data_center_id.eq_ignore_ascii_case("FROM_HOSTNAME") {
+            // NOTE(ayubun): unlike the worker ID, there's no convention for where the data center
+            // ID could be in a hostname, so this always needs a pattern
+            let pattern = args
+                .data_center_id_hostname_pattern
+                .as_deref()
+                .ok_or(ConfigError::MissingDataCenterIdHostnamePattern)?;
+            let pattern = compile_hostname_pattern("DATA_CENTER_ID_HOSTNAME_PATTERN", pattern)?;
+            id_from_hostname(&hostname, Some(&pattern))
+                .map_err(|_| ConfigError::InvalidHostnameDataCenterId { hostname })?
+        } else {
+            args.data_center_id
+                .parse::<u64>()
+                .map_err(|_| ConfigError::InvalidDataCenterId {
+                    value: args.data_center_id.clone(),
+                })?
+        };
+
+        let mut builder = WorkerConfig::builder()
+            .port(args.port)
+            .worker_id(worker_id)
+            .data_center_id(data_center_id)
+            .layout(layout)
+            .clock_regression_policy(
+                args.clock_regression_policy,
+                Duration::from_millis(args.clock_regression_max_wait_ms),
+            )
+            .state_file_interval(Duration::from_millis(args.state_file_interval_ms))
+            .high_water_mark_policy(args.high_water_mark_policy)
+            .generator_kind(args.generator)
+            .max_batch_size(args.max_batch_size)
+            .max_stream_size(args.max_stream_size)
+            .allow_get_generate(args.allow_get_generate)
+            .shutdown_drain(Duration::from_millis(args.shutdown_drain_ms))
+            .lease_key_prefix(args.lease_key_prefix)
+            .lease_ttl(Duration::from_millis(args.lease_ttl_ms))
+            .lease_data_center_id(lease_data_center_id)
+            .gossip_interface(args.gossip_interface)
+            .gossip_interval(Duration::from_millis(args.gossip_interval_ms))
+            .log_format(args.log_format)
+            .log_level(args.log_level);
+        if let Some(epoch) = args.epoch {
+            builder = builder.epoch(UNIX_EPOCH + Duration::from_millis(epoch));
+        }
+        if let Some(grpc_port) = args.grpc_port {
+            builder = builder.grpc_port(grpc_port);
+        }
+        if let Some(state_file) = args.state_file {
+            builder = builder.state_file(state_file);
+        }
+        if let Some(lease_store) = lease_store {
+            builder = builder.lease_store(lease_store);
+        }
+        if let Some(lockfile_dir) = lockfile_dir {
+            builder = builder.lockfile_dir(lockfile_dir);
+        }
+        if let Some(gossip_group) = args.gossip_group {
+            builder = builder.gossip_group(gossip_group);
+        }
+        builder.build()
+    }
+
+    /// The port that the HTTP API listens on.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// The port that the gRPC API listens on, if it's enabled.
+    pub fn grpc_port(&self) -> Option<u16> {
+        self.grpc_port
+    }
+
+    pub fn worker_id(&self) -> u64 {
+        self.worker_id
+    }
+
+    pub fn data_center_id(&self) -> u64 {
+        self.data_center_id
+    }
+
+    pub fn epoch(&self) -> SystemTime {
+        self.epoch
+    }
+
+    pub fn layout(&self) -> BitLayout {
+        self.layout
+    }
+
+    pub fn generator_kind(&self) -> GeneratorKind {
+        self.generator_kind
+    }
+
+    /// The largest number of IDs that a single request can ask for.
+    pub fn max_batch_size(&self) -> u64 {
+        self.max_batch_size
+    }
+
+    /// The largest number of IDs that a single streamed request can ask for.
+    pub fn max_stream_size(&self) -> u64 {
+        self.max_stream_size
+    }
+}
+
+/// Builds a [`WorkerConfig`], validating it in [`WorkerConfigBuilder::build`].
+#[derive(Debug, Clone)]
+pub struct WorkerConfigBuilder {
+    config: WorkerConfig,
+}
+
+impl Default for WorkerConfigBuilder {
+    fn default() -> Self {
+        WorkerConfigBuilder {
+            config: WorkerConfig {
+                port: DEFAULT_PORT,
+                grpc_port: None,
+                worker_id: 0,
+                data_center_id: 0,
+                epoch: DEFAULT_EPOCH,
+                layout: DEFAULT_LAYOUT,
+                clock_regression_policy: ClockRegressionPolicy::Wait,
+                clock_regression_max_wait: DEFAULT_CLOCK_REGRESSION_MAX_WAIT,
+                state_file: None,
+                state_file_interval: DEFAULT_STATE_FILE_INTERVAL,
+                high_water_mark_policy: HighWaterMarkPolicy::Wait,
+                generator_kind: GeneratorKind::Atomic,
+                max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+                max_stream_size: DEFAULT_MAX_STREAM_SIZE,
+                allow_get_generate: false,
+                shutdown_drain: DEFAULT_SHUTDOWN_DRAIN,
+                lease_store: None,
+                lease_key_prefix: DEFAULT_LEASE_KEY_PREFIX.to_string(),
+                lease_ttl: DEFAULT_LEASE_TTL,
+                lease_data_center_id: false,
+                lockfile_dir: None,
+                gossip_group: None,
+                gossip_interface: Ipv4Addr::UNSPECIFIED,
+                gossip_interval: DEFAULT_GOSSIP_INTERVAL,
+                log_format: LogFormat::Pretty,
+                log_level: DEFAULT_LOG_LEVEL.to_string(),
+            },
+        }
+    }
+}
+
+impl WorkerConfigBuilder {
+    /// The port that the HTTP API listens on. Defaults to `8080`.
+    pub fn port(mut self, port: u16) -> Self {
+        self.config.port = port;
+        self
+    }
+
+    /// The port that the gRPC API listens on. Defaults to none, which disables gRPC.
+    pub fn grpc_port(mut self, grpc_port: u16) -> Self {
+        self.config.grpc_port = Some(grpc_port);
+        self
+    }
+
+    /// Defaults to `0`.
+    pub fn worker_id(mut self, worker_id: u64) -> Self {
+        self.config.worker_id = worker_id;
+        self
+    }
+
+    /// Defaults to `0`.
+    pub fn data_center_id(mut self, data_center_id: u64) -> Self {
+        self.config.data_center_id = data_center_id;
+        self
+    }
+
+    /// The time that ID timestamps are counted from. Defaults to the UNIX epoch.
+    pub fn epoch(mut self, epoch: SystemTime) -> Self {
+        self.config.epoch = epoch;
+        self
+    }
+
+    /// Defaults to Twitter's original 41/5/5/12 bit layout.
+    pub fn layout(mut self, layout: BitLayout) -> Self {
+        self.config.layout = layout;
+        self
+    }
+
+    /// What to do when the clock moves backwards. `max_wait` only applies to
+    /// [`ClockRegressionPolicy::Wait`]. Defaults to waiting for up to 1 second.
+    pub fn clock_regression_policy(
+        mut self,
+        policy: ClockRegressionPolicy,
+        max_wait: Duration,
+    ) -> Self {
+        self.config.clock_regression_policy = policy;
+        self.config.clock_regression_max_wait = max_wait;
+        self
+    }
+
+    /// A file to persist the high-water mark timestamp to. Defaults to none.
+    pub fn state_file(mut self, state_file: impl Into<PathBuf>) -> Self {
+        self.config.state_file = Some(state_file.into());
+        self
+    }
+
+    /// How often the high-water mark is written to the state file. Defaults to 1 second.
+    pub fn state_file_interval(mut self, interval: Duration) -> Self {
+        self.config.state_file_interval = interval;
+        self
+    }
+
+    /// What to do on startup when the clock is behind the persisted high-water mark. Defaults
+    /// to waiting.
+    pub fn high_water_mark_policy(mut self, policy: HighWaterMarkPolicy) -> Self {
+        self.config.high_water_mark_policy = policy;
+        self
+    }
+
+    /// Which generator implementation to use. Defaults to [`GeneratorKind::Atomic`].
+    pub fn generator_kind(mut self, kind: GeneratorKind) -> Self {
+        self.config.generator_kind = kind;
+        self
+    }
+
+    /// The largest number of IDs that a single request can ask for. Defaults to `100000`.
+    pub fn max_batch_size(mut self, max_batch_size: u64) -> Self {
+        self.config.max_batch_size = max_batch_size;
+        self
+    }
+
+    /// The largest number of IDs that a single streamed request can ask for. Defaults to
+    /// `10000000`.
+    pub fn max_stream_size(mut self, max_stream_size: u64) -> Self {
+        self.config.max_stream_size = max_stream_size;
+        self
+    }
+
+    /// Whether `GET /generate?count=N` is served alongside `POST /generate`. Defaults to `false`.
+    pub fn allow_get_generate(mut self, allow: bool) -> Self {
+        self.config.allow_get_generate = allow;
+        self
+    }
+
+    /// How long the worker keeps serving requests after it's asked to shut down, while `/health`
+    /// reports it as shutting down. Defaults to 5 seconds.
+    pub fn shutdown_drain(mut self, drain: Duration) -> Self {
+        self.config.shutdown_drain = drain;
+        self
+    }
+
+    /// The URL of a Redis-compatible store that the worker binary claims its worker ID from,
+    /// instead of using [`worker_id`](Self::worker_id). Defaults to none.
+    pub fn lease_store(mut self, url: impl Into<String>) -> Self {
+        self.config.lease_store = Some(url.into());
+        self
+    }
+
+    /// What the keys in the lease store start with, so that several clusters can share a store.
+    /// Defaults to `snowflake-id-worker`.
+    pub fn lease_key_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.config.lease_key_prefix = prefix.into();
+        self
+    }
+
+    /// How long a worker ID lease lasts without being renewed. Defaults to 10 seconds.
+    pub fn lease_ttl(mut self, ttl: Duration) -> Self {
+        self.config.lease_ttl = ttl;
+        self
+    }
+
+    /// Whether the data center ID is leased along with the worker ID, instead of using
+    /// [`data_center_id`](Self::data_center_id). Defaults to `false`.
+    pub fn lease_data_center_id(mut self, lease: bool) -> Self {
+        self.config.lease_data_center_id = lease;
+        self
+    }
+
+    /// A directory shared by every worker on the host, which the worker binary locks a free worker
+    /// ID slot in, instead of using [`worker_id`](Self::worker_id). Defaults to none.
+    pub fn lockfile_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config.lockfile_dir = Some(dir.into());
+        self
+    }
+
+    /// A multicast group that the worker binary announces its identity on, and watches for other
+    /// workers with the same one. Defaults to none.
+    pub fn gossip_group(mut self, group: SocketAddrV4) -> Self {
+        self.config.gossip_group = Some(group);
+        self
+    }
+
+    /// The local interface to join [`gossip_group`](Self::gossip_group) on. Defaults to
+    /// [`Ipv4Addr::UNSPECIFIED`], which lets the OS pick one.
+    pub fn gossip_interface(mut self, interface: Ipv4Addr) -> Self {
+        self.config.gossip_interface = interface;
+        self
+    }
+
+    /// How often the worker announces itself on [`gossip_group`](Self::gossip_group). Defaults
+    /// to 1 second.
+    pub fn gossip_interval(mut self, interval: Duration) -> Self {
+        self.config.gossip_interval = interval;
+        self
+    }
+
+    /// How the worker binary writes its logs. Defaults to [`LogFormat::Pretty`].
+    pub fn log_format(mut self, format: LogFormat) -> Self {
+        self.config.log_format = format;
+        self
+    }
+
+    /// Which logs the worker binary writes, as a level or a list of `tracing` directives.
+    /// Defaults to `info`.
+    pub fn log_level(mut self, level: impl Into<String>) -> Self {
+        self.config.log_level = level.into();
+        self
+    }
+
+    /// Validates the config, returning a [`ConfigError`] if the IDs don't fit into the layout.
+    pub fn build(self) -> Result<WorkerConfig, ConfigError> {
+        let config = self.config;
+        let layout = config.layout;
+        layout.validate().map_err(ConfigError::InvalidLayout)?;
+        logging::validate_level(&config.log_level).map_err(|message| {
+            ConfigError::InvalidLogLevel {
+                value: config.log_level.clone(),
+                message,
+            }
+        })?;
+        if config.max_batch_size == 0 {
+            return Err(ConfigError::InvalidMaxBatchSize);
+        }
+        if config.max_stream_size == 0 {
+            return Err(ConfigError::InvalidMaxStreamSize);
+        }
+        if config.lease_ttl.is_zero() {
+            return Err(ConfigError::InvalidLeaseTtl);
+        }
+        if let Some(group) = config.gossip_group {
+            if !group.ip().is_multicast() {
+                return Err(ConfigError::InvalidGossipGroup { group });
+            }
+        }
+        if config.gossip_interval.is_zero() {
+            return Err(ConfigError::InvalidGossipInterval);
+        }
+
+        let max_data_center_id = layout.max_data_center_id();
+        if config.data_center_id > max_data_center_id {
+            return Err(ConfigError::DataCenterIdOutOfRange {
+                data_center_id: config.data_center_id,
+                max: max_data_center_id,
+            });
+        }
+
+        let max_worker_id = layout.max_worker_id();
+        if config.worker_id > max_worker_id {
+            return Err(ConfigError::WorkerIdOutOfRange {
+                worker_id: config.worker_id,
+                max: max_worker_id,
+            });
+        }
+
+        // NOTE(ayubun): a narrow timestamp field combined with an old EPOCH could mean that the
+        // current time doesn't fit into an ID at all, so we should refuse to start
+        let now_ms = SystemTime::now()
+            .duration_since(config.epoch)
+            .map_err(|_| ConfigError::EpochInFuture)?
+            .as_millis();
+        if now_ms > layout.max_timestamp() as u128 {
+            return Err(ConfigError::TimestampOverflow {
+                timestamp_bits: layout.timestamp_bits,
+            });
+        }
+
+        Ok(config)
+    }
+}
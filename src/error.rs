@@ -0,0 +1,326 @@
+use std::{
+    fmt, io,
+    net::{Ipv4Addr, SocketAddrV4},
+    path::PathBuf,
+};
+
+/// Everything that can be wrong with a worker's configuration.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The CLI arguments or environment variables couldn't be parsed at all
+    InvalidArgs(clap::Error),
+    /// `LOG_LEVEL` is neither a level nor a valid list of `tracing` directives
+    InvalidLogLevel { value: String, message: String },
+    /// `MAX_BATCH_SIZE` is `0`, which would reject every request
+    InvalidMaxBatchSize,
+    /// `MAX_STREAM_SIZE` is `0`, which would reject every streamed request
+    InvalidMaxStreamSize,
+    /// `LEASE_TTL_MS` is `0`, which would expire every lease right away
+    InvalidLeaseTtl,
+    /// `GOSSIP_GROUP` isn't a multicast address
+    InvalidGossipGroup { group: SocketAddrV4 },
+    /// `GOSSIP_INTERVAL_MS` is `0`, which would flood the network with announcements
+    InvalidGossipInterval,
+    /// `WORKER_ID` is neither an unsigned integer, `FROM_HOSTNAME`, `FROM_LEASE`, `FROM_LOCKFILE`
+    /// nor `FROM_IP`
+    InvalidWorkerId { value: String },
+    /// `WORKER_ID_HOSTNAME_PATTERN` or `DATA_CENTER_ID_HOSTNAME_PATTERN` isn't a valid regex, or
+    /// doesn't capture anything
+    InvalidHostnamePattern {
+        variable: &'static str,
+        message: String,
+    },
+    /// `WORKER_ID` is `FROM_HOSTNAME`, but the hostname has no `-` to split the worker ID from (or
+    /// doesn't match `WORKER_ID_HOSTNAME_PATTERN`)
+    HostnameWithoutWorkerId { hostname: String },
+    /// `WORKER_ID` is `FROM_HOSTNAME`, but the end of the hostname (or the part captured by
+    /// `WORKER_ID_HOSTNAME_PATTERN`) isn't an unsigned integer
+    InvalidHostnameWorkerId { hostname: String },
+    /// `WORKER_ID_OFFSET` would make the worker ID parsed from the hostname negative
+    WorkerIdOffsetOutOfRange { ordinal: u64, offset: i64 },
+    /// `WORKER_ID` is `FROM_LEASE`, but `LEASE_STORE_URL` isn't set
+    MissingLeaseStore,
+    /// `WORKER_ID` is `FROM_LOCKFILE`, but `LOCKFILE_DIR` isn't set
+    MissingLockfileDir,
+    /// `WORKER_ID` is `FROM_LEASE`, but the worker was created without claiming a lease (i.e. with
+    /// `Worker::new` rather than `Worker::with_allocator`)
+    LeaseNotClaimed,
+    /// A slot in `LOCKFILE_DIR` couldn't be created or locked
+    Lockfile { path: PathBuf, source: io::Error },
+    /// `WORKER_ID` is `FROM_LEASE` or `FROM_LOCKFILE`, but every worker ID is already taken by
+    /// another worker
+    NoFreeWorkerId { candidates: usize },
+    /// `IP_PREFIX_LEN` is longer than an IPv4 address
+    InvalidIpPrefixLen { prefix_len: u8 },
+    /// `WORKER_ID` is `FROM_IP`, but `POD_IP` isn't set and no local interface has a private IPv4
+    /// address
+    NoPrivateIp,
+    /// `WORKER_ID` is `FROM_IP`, but the bits of the IP after `IP_PREFIX_LEN` don't fit into
+    /// `WORKER_ID_BITS` (and `DATA_CENTER_ID_BITS`, with `DATA_CENTER_ID=FROM_IP`)
+    IpOutOfRange {
+        ip: Ipv4Addr,
+        prefix_len: u8,
+        host: u64,
+        max: u64,
+    },
+    /// `WORKER_ID` doesn't fit into the configured `WORKER_ID_BITS`
+    WorkerIdOutOfRange { worker_id: u64, max: u64 },
+    /// `DATA_CENTER_ID` is neither an unsigned integer, `FROM_HOSTNAME`, `FROM_LEASE` (with
+    /// `WORKER_ID=FROM_LEASE`) nor `FROM_IP` (with `WORKER_ID=FROM_IP`)
+    InvalidDataCenterId { value: String },
+    /// `DATA_CENTER_ID` is `FROM_HOSTNAME`, but `DATA_CENTER_ID_HOSTNAME_PATTERN` isn't set
+    MissingDataCenterIdHostnamePattern,
+    /// `DATA_CENTER_ID` is `FROM_HOSTNAME`, but `DATA_CENTER_ID_HOSTNAME_PATTERN` doesn't capture an
+    /// unsigned integer from the hostname
+    InvalidHostnameDataCenterId { hostname: String },
+    /// `DATA_CENTER_ID` doesn't fit into the configured `DATA_CENTER_ID_BITS`
+    DataCenterIdOutOfRange { data_center_id: u64, max: u64 },
+    /// The bit layout doesn't add up to 63 bits, or leaves no room for a timestamp
+    InvalidLayout(String),
+    /// `EPOCH` is later than the current time
+    EpochInFuture,
+    /// The current time since `EPOCH` doesn't fit into the configured `TIMESTAMP_BITS`
+    TimestampOverflow { timestamp_bits: u8 },
+    /// `STATE_FILE` exists, but couldn't be read
+    StateFile { path: PathBuf, source: io::Error },
+    /// The clock is behind the high-water mark in `STATE_FILE` (or left behind by the previous
+    /// holder of a leased worker ID), and `HIGH_WATER_MARK_POLICY` is `fail`
+    BehindHighWaterMark {
+        behind_ms: i64,
+        origin: &'static str,
+    },
+}
+
+impl ConfigError {
+    /// A distinct, non-zero process exit code for each class of failure.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            ConfigError::InvalidArgs(_)
+            | ConfigError::InvalidLogLevel { .. }
+            | ConfigError::InvalidMaxBatchSize
+            | ConfigError::InvalidMaxStreamSize
+            | ConfigError::InvalidLeaseTtl
+            | ConfigError::InvalidGossipGroup { .. }
+            | ConfigError::InvalidGossipInterval
+            | ConfigError::InvalidHostnamePattern { .. }
+            | ConfigError::InvalidIpPrefixLen { .. } => 2,
+            ConfigError::InvalidWorkerId { .. }
+            | ConfigError::HostnameWithoutWorkerId { .. }
+            | ConfigError::InvalidHostnameWorkerId { .. }
+            | ConfigError::WorkerIdOffsetOutOfRange { .. }
+            | ConfigError::MissingLeaseStore
+            | ConfigError::MissingLockfileDir
+            | ConfigError::LeaseNotClaimed
+            | ConfigError::Lockfile { .. }
+            | ConfigError::NoFreeWorkerId { .. }
+            | ConfigError::NoPrivateIp
+            | ConfigError::IpOutOfRange { .. }
+            | ConfigError::WorkerIdOutOfRange { .. } => 3,
+            ConfigError::InvalidDataCenterId { .. }
+            | ConfigError::MissingDataCenterIdHostnamePattern
+            | ConfigError::InvalidHostnameDataCenterId { .. }
+            | ConfigError::DataCenterIdOutOfRange { .. } => 4,
+            ConfigError::InvalidLayout(_)
+            | ConfigError::EpochInFuture
+            | ConfigError::TimestampOverflow { .. } => 5,
+            ConfigError::StateFile { .. } | ConfigError::BehindHighWaterMark { .. } => 6,
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::InvalidArgs(err) => write!(f, "{err}"),
+            ConfigError::InvalidLogLevel { value, message } => {
+                write!(f, "invalid LOG_LEVEL (LOG_LEVEL: \"{value}\"): {message}")
+            }
+            ConfigError::InvalidMaxBatchSize => {
+                write!(f, "MAX_BATCH_SIZE must be greater than 0")
+            }
+            ConfigError::InvalidMaxStreamSize => {
+                write!(f, "MAX_STREAM_SIZE must be greater than 0")
+            }
+            ConfigError::InvalidLeaseTtl => write!(f, "LEASE_TTL_MS must be greater than 0"),
+            ConfigError::InvalidGossipGroup { group } => write!(
+                f,
+                "GOSSIP_GROUP must be a multicast address (GOSSIP_GROUP: {group})"
+            ),
+            ConfigError::InvalidGossipInterval => {
+                write!(f, "GOSSIP_INTERVAL_MS must be greater than 0")
+            }
+            ConfigError::InvalidWorkerId { value } => write!(
+                f,
+                "cannot parse WORKER_ID as a valid unsigned integer (WORKER_ID: \"{value}\")"
+            ),
+            ConfigError::InvalidHostnamePattern { variable, message } => {
+                write!(f, "invalid {variable}: {message}")
+            }
+            ConfigError::HostnameWithoutWorkerId { hostname } => write!(
+                f,
+                "cannot split WORKER_ID from hostname (WORKER_ID is being parsed from hostname: \"{hostname}\")"
+            ),
+            ConfigError::InvalidHostnameWorkerId { hostname } => write!(
+                f,
+                "cannot parse WORKER_ID from hostname (WORKER_ID is being parsed from hostname: \"{hostname}\")"
+            ),
+            ConfigError::WorkerIdOffsetOutOfRange { ordinal, offset } => write!(
+                f,
+                "WORKER_ID_OFFSET ({offset}) is out of range for the WORKER_ID parsed from hostname ({ordinal})"
+            ),
+            ConfigError::MissingLeaseStore => {
+                write!(f, "LEASE_STORE_URL must be set when WORKER_ID is \"FROM_LEASE\"")
+            }
+            ConfigError::MissingLockfileDir => {
+                write!(f, "LOCKFILE_DIR must be set when WORKER_ID is \"FROM_LOCKFILE\"")
+            }
+            ConfigError::LeaseNotClaimed => write!(
+                f,
+                "WORKER_ID is \"FROM_LEASE\", so the worker must claim a lease with \
+                 Worker::with_allocator before generating IDs"
+            ),
+            ConfigError::Lockfile { path, source } => {
+                write!(f, "cannot lock a WORKER_ID slot in LOCKFILE_DIR ({path:?}): {source}")
+            }
+            ConfigError::NoFreeWorkerId { candidates } => write!(
+                f,
+                "every WORKER_ID is already taken by another worker ({candidates} tried)"
+            ),
+            ConfigError::InvalidIpPrefixLen { prefix_len } => write!(
+                f,
+                "IP_PREFIX_LEN must be less than or equal to 32 (IP_PREFIX_LEN: {prefix_len})"
+            ),
+            ConfigError::NoPrivateIp => write!(
+                f,
+                "POD_IP must be set when WORKER_ID is \"FROM_IP\" and no local interface has a private IPv4 address"
+            ),
+            ConfigError::IpOutOfRange {
+                ip,
+                prefix_len,
+                host,
+                max,
+            } => write!(
+                f,
+                "the host part of {ip}/{prefix_len} ({host}) is too large for a WORKER_ID (max: {max}); check IP_PREFIX_LEN and WORKER_ID_BITS"
+            ),
+            ConfigError::WorkerIdOutOfRange { worker_id, max } => write!(
+                f,
+                "WORKER_ID must be less than or equal to {max} (WORKER_ID: {worker_id})"
+            ),
+            ConfigError::InvalidDataCenterId { value } => write!(
+                f,
+                "cannot parse DATA_CENTER_ID as a valid unsigned integer (DATA_CENTER_ID: \"{value}\")"
+            ),
+            ConfigError::MissingDataCenterIdHostnamePattern => write!(
+                f,
+                "DATA_CENTER_ID_HOSTNAME_PATTERN must be set when DATA_CENTER_ID is \"FROM_HOSTNAME\""
+            ),
+            ConfigError::InvalidHostnameDataCenterId { hostname } => write!(
+                f,
+                "cannot parse DATA_CENTER_ID from hostname with DATA_CENTER_ID_HOSTNAME_PATTERN (hostname: \"{hostname}\")"
+            ),
+            ConfigError::DataCenterIdOutOfRange {
+                data_center_id,
+                max,
+            } => write!(
+                f,
+                "DATA_CENTER_ID must be less than or equal to {max} (DATA_CENTER_ID: {data_center_id})"
+            ),
+            ConfigError::InvalidLayout(message) => write!(f, "invalid bit layout: {message}"),
+            ConfigError::EpochInFuture => write!(f, "EPOCH must not be in the future"),
+            ConfigError::TimestampOverflow { timestamp_bits } => write!(
+                f,
+                "TIMESTAMP_BITS ({timestamp_bits}) is too small to represent the current time since EPOCH"
+            ),
+            ConfigError::StateFile { path, source } => {
+                write!(f, "cannot read STATE_FILE (STATE_FILE: {path:?}): {source}")
+            }
+            ConfigError::BehindHighWaterMark { behind_ms, origin } => write!(
+                f,
+                "clock is {behind_ms}ms behind the high-water mark in {origin} (HIGH_WATER_MARK_POLICY is \"fail\")"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::InvalidArgs(err) => Some(err),
+            ConfigError::StateFile { source, .. } => Some(source),
+            ConfigError::Lockfile { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Everything that can stop a worker, either while it starts up or while it's running (see
+/// [`run_worker`](crate::run_worker)).
+#[derive(Debug)]
+pub enum WorkerError {
+    /// The worker's configuration is invalid
+    Config(ConfigError),
+    /// `LEASE_STORE_URL` couldn't be reached, or failed while a worker ID was being claimed
+    LeaseStore { message: String },
+    /// The HTTP server couldn't listen on `PORT`
+    Bind { port: u16, source: warp::Error },
+    /// The gRPC server couldn't listen on `GRPC_PORT`, or stopped unexpectedly
+    Grpc { port: u16, source: io::Error },
+    /// The worker couldn't join `GOSSIP_GROUP`
+    Gossip {
+        group: SocketAddrV4,
+        source: io::Error,
+    },
+}
+
+impl WorkerError {
+    /// A distinct, non-zero process exit code for each class of failure.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            WorkerError::Config(err) => err.exit_code(),
+            WorkerError::LeaseStore { .. } => 3,
+            WorkerError::Bind { .. } | WorkerError::Grpc { .. } | WorkerError::Gossip { .. } => 7,
+        }
+    }
+}
+
+impl From<ConfigError> for WorkerError {
+    fn from(err: ConfigError) -> WorkerError {
+        WorkerError::Config(err)
+    }
+}
+
+impl fmt::Display for WorkerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WorkerError::Config(err) => err.fmt(f),
+            WorkerError::LeaseStore { message } => {
+                write!(
+                    f,
+                    "cannot claim a WORKER_ID from LEASE_STORE_URL: {message}"
+                )
+            }
+            WorkerError::Bind { port, source } => {
+                write!(f, "cannot listen on PORT {port}: {source}")
+            }
+            WorkerError::Grpc { port, source } => {
+                write!(f, "cannot serve gRPC on GRPC_PORT {port}: {source}")
+            }
+            WorkerError::Gossip { group, source } => {
+                write!(f, "cannot join GOSSIP_GROUP {group}: {source}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for WorkerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            WorkerError::Config(err) => err.source(),
+            WorkerError::Bind { source, .. } => Some(source),
+            WorkerError::Grpc { source, .. } => Some(source),
+            WorkerError::Gossip { source, .. } => Some(source),
+            WorkerError::LeaseStore { .. } => None,
+        }
+    }
+}
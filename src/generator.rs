@@ -0,0 +1,614 @@
+use std::{
+    hint::spin_loop,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    atomic_generator::AtomicSnowflakeIdGenerator,
+    clock::{self, ClockMonitor, ClockRegressionError, ClockRegressionPolicy},
+    metrics::Metrics,
+    GenerateError, WorkerConfig,
+};
+
+/// The number of bits available to a snowflake ID. The top bit is never set so that IDs stay
+/// positive when they are stored as signed 64-bit integers.
+pub(crate) const ID_BITS: u8 = 63;
+
+/// The widths of each field in a snowflake ID, from most to least significant:
+///
+/// | 1 unused bit | timestamp | data center id | worker id | sequence |
+///
+/// The default layout matches Twitter's original snowflake algorithm (41/5/5/12).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BitLayout {
+    pub timestamp_bits: u8,
+    pub data_center_id_bits: u8,
+    pub worker_id_bits: u8,
+    pub sequence_bits: u8,
+}
+
+pub(crate) const DEFAULT_LAYOUT: BitLayout = BitLayout {
+    timestamp_bits: 41,
+    data_center_id_bits: 5,
+    worker_id_bits: 5,
+    sequence_bits: 12,
+};
+
+impl Default for BitLayout {
+    fn default() -> Self {
+        DEFAULT_LAYOUT
+    }
+}
+
+/// The parts of a snowflake ID, as laid out by a [`BitLayout`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SnowflakeParts {
+    pub(crate) timestamp_ms: i64,
+    pub(crate) data_center_id: u64,
+    pub(crate) worker_id: u64,
+    pub(crate) sequence: u64,
+}
+
+impl BitLayout {
+    /// Returns an error message if the fields don't add up to exactly 63 bits, or if the layout
+    /// leaves no room for a timestamp.
+    pub fn validate(&self) -> Result<(), String> {
+        let total = self.timestamp_bits as u32
+            + self.data_center_id_bits as u32
+            + self.worker_id_bits as u32
+            + self.sequence_bits as u32;
+        if total != ID_BITS as u32 {
+            return Err(format!(
+                "bit layout must add up to {ID_BITS} bits, but TIMESTAMP_BITS ({}) + DATA_CENTER_ID_BITS ({}) + WORKER_ID_BITS ({}) + SEQUENCE_BITS ({}) = {total}",
+                self.timestamp_bits, self.data_center_id_bits, self.worker_id_bits, self.sequence_bits
+            ));
+        }
+        if self.timestamp_bits == 0 {
+            return Err("TIMESTAMP_BITS must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn max_timestamp(&self) -> i64 {
+        mask(self.timestamp_bits) as i64
+    }
+
+    pub fn max_data_center_id(&self) -> u64 {
+        mask(self.data_center_id_bits)
+    }
+
+    pub fn max_worker_id(&self) -> u64 {
+        mask(self.worker_id_bits)
+    }
+
+    pub fn max_sequence(&self) -> u64 {
+        mask(self.sequence_bits)
+    }
+
+    #[inline(always)]
+    fn worker_id_shift(&self) -> u8 {
+        self.sequence_bits
+    }
+
+    #[inline(always)]
+    fn data_center_id_shift(&self) -> u8 {
+        self.worker_id_shift() + self.worker_id_bits
+    }
+
+    #[inline(always)]
+    fn timestamp_shift(&self) -> u8 {
+        self.data_center_id_shift() + self.data_center_id_bits
+    }
+
+    /// Makes sure that `timestamp_ms` still fits into the timestamp field. Composing an ID with a
+    /// timestamp that doesn't would spill it into the sign bit and the other fields.
+    #[inline(always)]
+    pub(crate) fn check_timestamp(&self, timestamp_ms: i64) -> Result<(), GenerateError> {
+        if timestamp_ms > self.max_timestamp() {
+            return Err(GenerateError::TimestampOverflow {
+                timestamp_bits: self.timestamp_bits,
+            });
+        }
+        Ok(())
+    }
+
+    #[inline(always)]
+    pub(crate) fn compose(&self, parts: SnowflakeParts) -> i64 {
+        ((parts.timestamp_ms as u64) << self.timestamp_shift()
+            | parts.data_center_id << self.data_center_id_shift()
+            | parts.worker_id << self.worker_id_shift()
+            | parts.sequence) as i64
+    }
+
+    pub(crate) fn decompose(&self, id: i64) -> SnowflakeParts {
+        let id = id as u64;
+        SnowflakeParts {
+            timestamp_ms: ((id >> self.timestamp_shift()) & mask(self.timestamp_bits)) as i64,
+            data_center_id: (id >> self.data_center_id_shift()) & self.max_data_center_id(),
+            worker_id: (id >> self.worker_id_shift()) & self.max_worker_id(),
+            sequence: id & self.max_sequence(),
+        }
+    }
+}
+
+#[inline(always)]
+fn mask(bits: u8) -> u64 {
+    // NOTE(ayubun): `1 << 64` overflows, so fields as wide as the whole ID need special casing
+    if bits >= 64 {
+        u64::MAX
+    } else {
+        (1 << bits) - 1
+    }
+}
+
+/// Returns the number of milliseconds that have passed since `epoch`. This is negative if the
+/// clock has been stepped back to before `epoch`, which the generators treat like any other clock
+/// regression, since they never issue IDs with a timestamp below `0`.
+#[inline(always)]
+pub(crate) fn millis_since(epoch: SystemTime) -> i64 {
+    match SystemTime::now().duration_since(epoch) {
+        Ok(since) => since.as_millis() as i64,
+        Err(err) => -(err.duration().as_millis() as i64),
+    }
+}
+
+/// A snowflake ID generator that lays out its IDs according to a [`BitLayout`].
+pub(crate) struct SnowflakeIdGenerator {
+    layout: BitLayout,
+    epoch: SystemTime,
+    data_center_id: u64,
+    worker_id: u64,
+    last_timestamp_ms: i64,
+    sequence: u64,
+    clock_regression_policy: ClockRegressionPolicy,
+    clock_regression_max_wait: Duration,
+    clock_monitor: Arc<ClockMonitor>,
+    metrics: Arc<Metrics>,
+}
+
+impl SnowflakeIdGenerator {
+    pub(crate) fn new(
+        layout: BitLayout,
+        epoch: SystemTime,
+        data_center_id: u64,
+        worker_id: u64,
+    ) -> SnowflakeIdGenerator {
+        SnowflakeIdGenerator {
+            layout,
+            epoch,
+            data_center_id,
+            worker_id,
+            // NOTE(ayubun): starting at 0 rather than -1 means that a clock stepped back to before
+            // `epoch` counts as a regression, even before the first ID is issued
+            last_timestamp_ms: 0,
+            sequence: 0,
+            clock_regression_policy: ClockRegressionPolicy::Fail,
+            clock_regression_max_wait: Duration::ZERO,
+            clock_monitor: Arc::new(ClockMonitor::new(epoch)),
+            metrics: Arc::new(Metrics::new(worker_id, data_center_id)),
+        }
+    }
+
+    pub(crate) fn from_config(config: &WorkerConfig) -> SnowflakeIdGenerator {
+        SnowflakeIdGenerator::new(
+            config.layout,
+            config.epoch,
+            config.data_center_id,
+            config.worker_id,
+        )
+        .with_clock_regression_policy(
+            config.clock_regression_policy,
+            config.clock_regression_max_wait,
+        )
+    }
+
+    /// Sets what the generator should do when the clock moves backwards. `max_wait` only applies
+    /// to [`ClockRegressionPolicy::Wait`].
+    pub(crate) fn with_clock_regression_policy(
+        mut self,
+        policy: ClockRegressionPolicy,
+        max_wait: Duration,
+    ) -> SnowflakeIdGenerator {
+        self.clock_regression_policy = policy;
+        self.clock_regression_max_wait = max_wait;
+        self
+    }
+
+    pub(crate) fn clock_monitor(&self) -> Arc<ClockMonitor> {
+        self.clock_monitor.clone()
+    }
+
+    pub(crate) fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    /// Makes sure that no IDs are issued at or below `mark_ms`, which may have been issued by a
+    /// previous run of the worker.
+    pub(crate) fn restore_high_water_mark(&mut self, mark_ms: i64) {
+        if mark_ms >= self.last_timestamp_ms {
+            self.last_timestamp_ms = mark_ms;
+            // NOTE(ayubun): marking the sequence as used up forces the next ID into a later
+            // millisecond, since we don't know which sequences were issued at the mark
+            self.sequence = self.layout.max_sequence();
+            self.clock_monitor.record_issued(mark_ms);
+        }
+    }
+
+    /// Pretends that the last ID was issued at `timestamp_ms`. Pretending that it was issued in
+    /// the future is the same as the clock having moved backwards since then.
+    #[cfg(test)]
+    pub(crate) fn pretend_last_issued_at(&mut self, timestamp_ms: i64) {
+        self.last_timestamp_ms = timestamp_ms;
+        self.clock_monitor.record_issued(timestamp_ms);
+    }
+
+    /// Generates a new ID using the current time. If the sequence for the current millisecond has
+    /// been used up, this busy waits until the next millisecond.
+    ///
+    /// If the clock has moved backwards since the last ID was issued, the configured
+    /// [`ClockRegressionPolicy`] decides whether this blocks, reuses the last timestamp or fails.
+    ///
+    /// Once the timestamp no longer fits into the layout, every call fails with
+    /// [`GenerateError::TimestampOverflow`].
+    pub(crate) fn generate(&mut self) -> Result<i64, GenerateError> {
+        let mut now_ms = millis_since(self.epoch);
+
+        let clock_is_behind = now_ms < self.last_timestamp_ms;
+        if clock_is_behind {
+            now_ms = self.handle_clock_regression(now_ms)?;
+        } else {
+            self.clock_monitor.record_caught_up();
+        }
+
+        if now_ms == self.last_timestamp_ms {
+            self.sequence = (self.sequence + 1) & self.layout.max_sequence();
+            if self.sequence == 0 {
+                now_ms = if clock_is_behind
+                    && self.clock_regression_policy == ClockRegressionPolicy::Hold
+                {
+                    // NOTE(ayubun): the clock is behind, so there's no point waiting for the next
+                    // millisecond. instead, we borrow it from the future
+                    self.last_timestamp_ms + 1
+                } else {
+                    let waiting_since = Instant::now();
+                    let next_ms = wait_for_next_millis(self.last_timestamp_ms, self.epoch);
+                    self.metrics
+                        .sequence_exhaustion_wait
+                        .observe(waiting_since.elapsed().as_secs_f64());
+                    next_ms
+                };
+            }
+        } else {
+            self.sequence = 0;
+        }
+        self.layout.check_timestamp(now_ms)?;
+        self.last_timestamp_ms = now_ms;
+        self.clock_monitor.record_issued(now_ms);
+
+        Ok(self.layout.compose(SnowflakeParts {
+            timestamp_ms: now_ms,
+            data_center_id: self.data_center_id,
+            worker_id: self.worker_id,
+            sequence: self.sequence,
+        }))
+    }
+
+    /// Returns the timestamp to issue the next ID with, given that the clock reads `now_ms`,
+    /// which is earlier than the last issued ID.
+    fn handle_clock_regression(&mut self, now_ms: i64) -> Result<i64, ClockRegressionError> {
+        let drift_ms = self.last_timestamp_ms - now_ms;
+        if self
+            .clock_monitor
+            .record_regression(drift_ms, self.clock_regression_policy)
+        {
+            self.metrics.clock_regressions.inc();
+        }
+
+        match self.clock_regression_policy {
+            ClockRegressionPolicy::Wait
+                if drift_ms as u128 <= self.clock_regression_max_wait.as_millis() =>
+            {
+                Ok(clock::wait_until(self.last_timestamp_ms, self.epoch))
+            }
+            ClockRegressionPolicy::Hold => Ok(self.last_timestamp_ms),
+            ClockRegressionPolicy::Wait | ClockRegressionPolicy::Fail => {
+                Err(ClockRegressionError { drift_ms })
+            }
+        }
+    }
+}
+
+// Constantly refreshing the latest milliseconds by busy waiting.
+#[inline(always)]
+fn wait_for_next_millis(last_timestamp_ms: i64, epoch: SystemTime) -> i64 {
+    loop {
+        let now_ms = millis_since(epoch);
+        if now_ms > last_timestamp_ms {
+            return now_ms;
+        }
+        spin_loop();
+    }
+}
+
+/// Which generator implementation a worker should use. Both issue exactly the same IDs, so this
+/// only matters for performance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum GeneratorKind {
+    /// Advance a packed timestamp and sequence with compare-and-swap, so generating never locks
+    Atomic,
+    /// Guard the generator with a mutex
+    Mutex,
+}
+
+/// The generator behind a worker, as picked by [`GeneratorKind`].
+pub(crate) enum IdGenerator {
+    Atomic(AtomicSnowflakeIdGenerator),
+    // NOTE(ayubun): the metrics are kept outside of the mutex so that the time spent waiting for
+    // it can be recorded without holding it
+    Mutex(Mutex<SnowflakeIdGenerator>, Arc<Metrics>),
+}
+
+impl IdGenerator {
+    pub(crate) fn from_config(config: &WorkerConfig) -> IdGenerator {
+        match config.generator_kind {
+            GeneratorKind::Atomic => {
+                IdGenerator::Atomic(AtomicSnowflakeIdGenerator::from_config(config))
+            }
+            GeneratorKind::Mutex => SnowflakeIdGenerator::from_config(config).into(),
+        }
+    }
+
+    pub(crate) fn clock_monitor(&self) -> Arc<ClockMonitor> {
+        match self {
+            IdGenerator::Atomic(generator) => generator.clock_monitor(),
+            IdGenerator::Mutex(generator, _) => generator.lock().unwrap().clock_monitor(),
+        }
+    }
+
+    pub(crate) fn metrics(&self) -> Arc<Metrics> {
+        match self {
+            IdGenerator::Atomic(generator) => generator.metrics(),
+            IdGenerator::Mutex(_, metrics) => metrics.clone(),
+        }
+    }
+
+    fn lock<'a>(
+        generator: &'a Mutex<SnowflakeIdGenerator>,
+        metrics: &Metrics,
+    ) -> std::sync::MutexGuard<'a, SnowflakeIdGenerator> {
+        let waiting_since = Instant::now();
+        let unlocked_generator = generator.lock().unwrap();
+        metrics
+            .mutex_wait
+            .observe(waiting_since.elapsed().as_secs_f64());
+        unlocked_generator
+    }
+
+    pub(crate) fn restore_high_water_mark(&self, mark_ms: i64) {
+        match self {
+            IdGenerator::Atomic(generator) => generator.restore_high_water_mark(mark_ms),
+            IdGenerator::Mutex(generator, _) => {
+                generator.lock().unwrap().restore_high_water_mark(mark_ms)
+            }
+        }
+    }
+
+    pub(crate) fn generate(&self) -> Result<i64, GenerateError> {
+        match self {
+            IdGenerator::Atomic(generator) => generator.generate(),
+            IdGenerator::Mutex(generator, metrics) => {
+                IdGenerator::lock(generator, metrics).generate()
+            }
+        }
+    }
+
+    pub(crate) fn generate_batch(&self, count: usize) -> Result<Vec<i64>, GenerateError> {
+        match self {
+            IdGenerator::Atomic(generator) => generator.generate_batch(count),
+            IdGenerator::Mutex(generator, metrics) => {
+                let mut ids = Vec::with_capacity(count);
+                let mut unlocked_generator = IdGenerator::lock(generator, metrics);
+                for _ in 0..count {
+                    ids.push(unlocked_generator.generate()?);
+                }
+                Ok(ids)
+            }
+        }
+    }
+}
+
+impl From<SnowflakeIdGenerator> for IdGenerator {
+    fn from(generator: SnowflakeIdGenerator) -> IdGenerator {
+        let metrics = generator.metrics();
+        IdGenerator::Mutex(Mutex::new(generator), metrics)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn test_default_layout_is_valid() {
+        assert_eq!(DEFAULT_LAYOUT.validate(), Ok(()));
+        assert_eq!(DEFAULT_LAYOUT.max_data_center_id(), 31);
+        assert_eq!(DEFAULT_LAYOUT.max_worker_id(), 31);
+        assert_eq!(DEFAULT_LAYOUT.max_sequence(), 4095);
+    }
+
+    #[test]
+    fn test_layout_must_add_up_to_63_bits() {
+        let layout = BitLayout {
+            timestamp_bits: 41,
+            data_center_id_bits: 5,
+            worker_id_bits: 5,
+            sequence_bits: 13,
+        };
+        assert!(layout.validate().unwrap_err().contains("= 64"));
+
+        let layout = BitLayout {
+            timestamp_bits: 0,
+            data_center_id_bits: 0,
+            worker_id_bits: 31,
+            sequence_bits: 32,
+        };
+        assert!(layout.validate().is_err());
+    }
+
+    #[test]
+    fn test_compose_decompose_round_trip() {
+        let layout = BitLayout {
+            timestamp_bits: 41,
+            data_center_id_bits: 0,
+            worker_id_bits: 10,
+            sequence_bits: 12,
+        };
+        assert_eq!(layout.validate(), Ok(()));
+
+        let parts = SnowflakeParts {
+            timestamp_ms: layout.max_timestamp(),
+            data_center_id: 0,
+            worker_id: 1000,
+            sequence: 4095,
+        };
+        let id = layout.compose(parts);
+        assert!(id > 0);
+        assert_eq!(layout.decompose(id), parts);
+    }
+
+    #[test]
+    fn test_generator_with_small_sequence() {
+        // NOTE(ayubun): with only 2 sequence bits, the generator has to wait for the next
+        // millisecond every 4 IDs
+        let layout = BitLayout {
+            timestamp_bits: 41,
+            data_center_id_bits: 10,
+            worker_id_bits: 10,
+            sequence_bits: 2,
+        };
+        let mut generator = SnowflakeIdGenerator::new(layout, UNIX_EPOCH, 1023, 512);
+
+        let ids: Vec<i64> = (0..100).map(|_| generator.generate().unwrap()).collect();
+        let unique_ids: HashSet<i64> = ids.iter().cloned().collect();
+        assert_eq!(unique_ids.len(), ids.len(), "All IDs should be unique");
+
+        for id in ids {
+            let parts = layout.decompose(id);
+            assert_eq!(parts.data_center_id, 1023);
+            assert_eq!(parts.worker_id, 512);
+            assert!(parts.sequence <= 3);
+        }
+    }
+
+    #[test]
+    fn test_generator_refuses_to_overflow_the_timestamp() {
+        // NOTE(ayubun): 10 timestamp bits only last for ~1 second, so a worker that started just
+        // before then runs out of timestamps almost right away
+        let layout = BitLayout {
+            timestamp_bits: 10,
+            data_center_id_bits: 10,
+            worker_id_bits: 10,
+            sequence_bits: 33,
+        };
+        let epoch = SystemTime::now() - Duration::from_millis(1_000);
+        let mut generator = SnowflakeIdGenerator::new(layout, epoch, 1, 1);
+        let id = generator.generate().unwrap();
+        assert!(id > 0);
+        assert_eq!(layout.decompose(id).worker_id, 1);
+
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(
+            generator.generate(),
+            Err(GenerateError::TimestampOverflow { timestamp_bits: 10 })
+        );
+    }
+
+    #[test]
+    fn test_clock_before_epoch_is_a_regression() {
+        let epoch = SystemTime::now() + Duration::from_millis(10_000);
+        let mut generator = SnowflakeIdGenerator::new(DEFAULT_LAYOUT, epoch, 0, 0);
+        assert!(millis_since(epoch) < 0);
+        assert!(matches!(
+            generator.generate(),
+            Err(GenerateError::ClockRegression(_))
+        ));
+    }
+
+    fn generator_with_regressed_clock(
+        policy: ClockRegressionPolicy,
+        max_wait: Duration,
+        drift_ms: i64,
+    ) -> SnowflakeIdGenerator {
+        let mut generator = SnowflakeIdGenerator::new(DEFAULT_LAYOUT, UNIX_EPOCH, 0, 0)
+            .with_clock_regression_policy(policy, max_wait);
+        generator.pretend_last_issued_at(millis_since(UNIX_EPOCH) + drift_ms);
+        generator
+    }
+
+    #[test]
+    fn test_clock_regression_fail_policy() {
+        let mut generator =
+            generator_with_regressed_clock(ClockRegressionPolicy::Fail, Duration::ZERO, 10_000);
+
+        let Err(GenerateError::ClockRegression(err)) = generator.generate() else {
+            panic!("the clock regression should have been detected");
+        };
+        assert!(err.drift_ms > 9_000 && err.drift_ms <= 10_000);
+        assert!(generator.generate().is_err());
+
+        let monitor = generator.clock_monitor();
+        assert_eq!(
+            monitor.events(),
+            1,
+            "One regression should count as one event"
+        );
+        assert!(monitor.current_drift_ms().is_some());
+    }
+
+    #[test]
+    fn test_clock_regression_wait_policy() {
+        let mut generator = generator_with_regressed_clock(
+            ClockRegressionPolicy::Wait,
+            Duration::from_millis(1_000),
+            50,
+        );
+        let last_timestamp_ms = generator.last_timestamp_ms;
+
+        let id = generator.generate().unwrap();
+        assert!(DEFAULT_LAYOUT.decompose(id).timestamp_ms >= last_timestamp_ms);
+        assert!(millis_since(UNIX_EPOCH) >= last_timestamp_ms);
+        assert_eq!(generator.clock_monitor().events(), 1);
+
+        // NOTE(ayubun): the clock is now too far behind to wait it out
+        let mut generator = generator_with_regressed_clock(
+            ClockRegressionPolicy::Wait,
+            Duration::from_millis(10),
+            10_000,
+        );
+        assert!(generator.generate().is_err());
+    }
+
+    #[test]
+    fn test_clock_regression_hold_policy() {
+        let mut generator =
+            generator_with_regressed_clock(ClockRegressionPolicy::Hold, Duration::ZERO, 10_000);
+        let last_timestamp_ms = generator.last_timestamp_ms;
+
+        // NOTE(ayubun): generating more IDs than fit into one millisecond forces the generator
+        // to borrow timestamps from the future while it holds
+        let ids: Vec<i64> = (0..10_000).map(|_| generator.generate().unwrap()).collect();
+        let unique_ids: HashSet<i64> = ids.iter().cloned().collect();
+        assert_eq!(unique_ids.len(), ids.len(), "All IDs should be unique");
+        assert!(
+            ids.windows(2).all(|w| w[0] < w[1]),
+            "IDs should keep increasing"
+        );
+        assert!(ids
+            .iter()
+            .all(|&id| DEFAULT_LAYOUT.decompose(id).timestamp_ms >= last_timestamp_ms));
+        assert_eq!(generator.clock_monitor().events(), 1);
+    }
+}
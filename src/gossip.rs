@@ -0,0 +1,340 @@
+//! Watches for other workers with the same identity over UDP multicast, for `GOSSIP_GROUP`.
+//!
+//! Every worker announces its identity, `EPOCH` and start time on the group every
+//! `GOSSIP_INTERVAL_MS`. A worker that hears another one announce the same identity (or a
+//! different `EPOCH`) fails `/readyz` until that peer has been quiet for a few intervals, since
+//! the two of them could be issuing the same IDs.
+
+use std::{
+    collections::HashMap,
+    hash::{BuildHasher, RandomState},
+    io,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+
+use crate::{WorkerConfig, WorkerError};
+
+// NOTE(ayubun): a peer is only forgotten after missing this many announcements in a row, so that a
+// dropped packet or two doesn't flip readiness back and forth
+const MISSED_ANNOUNCEMENTS: u32 = 3;
+
+/// What every worker sends to the group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct Announcement {
+    /// Unique to each worker, so that a worker can ignore its own announcements
+    instance: u64,
+    data_center_id: u64,
+    worker_id: u64,
+    epoch_ms: u64,
+    started_at_ms: u64,
+}
+
+impl Announcement {
+    fn conflict_with(&self, peer: &Announcement) -> Option<&'static str> {
+        if self.instance == peer.instance {
+            None
+        } else if (self.data_center_id, self.worker_id) == (peer.data_center_id, peer.worker_id) {
+            Some("the same worker ID")
+        } else if self.epoch_ms != peer.epoch_ms {
+            Some("a different EPOCH")
+        } else {
+            None
+        }
+    }
+}
+
+/// A peer whose announcements conflict with ours.
+struct Conflict {
+    reason: &'static str,
+    address: SocketAddr,
+    announcement: Announcement,
+    last_seen: Instant,
+}
+
+impl Conflict {
+    fn describe(&self) -> String {
+        let started_at = UNIX_EPOCH + Duration::from_millis(self.announcement.started_at_ms);
+        format!(
+            "peer at {} (started at {}) announced {}: worker ID {}, data center ID {}, EPOCH {}",
+            self.address,
+            humantime::format_rfc3339_millis(started_at),
+            self.reason,
+            self.announcement.worker_id,
+            self.announcement.data_center_id,
+            self.announcement.epoch_ms,
+        )
+    }
+}
+
+/// A worker's membership of `GOSSIP_GROUP`, which keeps announcing it for as long as this is
+/// held onto.
+pub(crate) struct Gossip {
+    announcement: Announcement,
+    expire_after: Duration,
+    conflicts: Mutex<HashMap<u64, Conflict>>,
+}
+
+impl Gossip {
+    /// Describes a peer that currently conflicts with this worker, if there are any.
+    pub(crate) fn conflict(&self) -> Option<String> {
+        let conflicts = self.conflicts.lock().unwrap();
+        conflicts
+            .values()
+            .filter(|conflict| conflict.last_seen.elapsed() < self.expire_after)
+            .min_by_key(|conflict| conflict.announcement.instance)
+            .map(Conflict::describe)
+    }
+
+    fn observe(&self, payload: &[u8], address: SocketAddr) {
+        let Ok(peer) = serde_json::from_slice::<Announcement>(payload) else {
+            tracing::debug!(%address, "ignoring malformed gossip announcement");
+            return;
+        };
+        let Some(reason) = self.announcement.conflict_with(&peer) else {
+            return;
+        };
+
+        let mut conflicts = self.conflicts.lock().unwrap();
+        let conflict = Conflict {
+            reason,
+            address,
+            announcement: peer,
+            last_seen: Instant::now(),
+        };
+        let known = conflicts
+            .get(&peer.instance)
+            .is_some_and(|known| known.last_seen.elapsed() < self.expire_after);
+        if !known {
+            tracing::error!(
+                peer = conflict.describe(),
+                worker_id = self.announcement.worker_id,
+                data_center_id = self.announcement.data_center_id,
+                "CONFLICTING PEER ON GOSSIP_GROUP! the two workers could be issuing the same IDs, \
+                 so this one is no longer ready"
+            );
+        }
+        conflicts.insert(peer.instance, conflict);
+    }
+
+    /// Forgets peers that stopped announcing themselves.
+    fn expire(&self) {
+        self.conflicts.lock().unwrap().retain(|_, conflict| {
+            let active = conflict.last_seen.elapsed() < self.expire_after;
+            if !active {
+                tracing::info!(
+                    peer = %conflict.address,
+                    "conflicting peer stopped announcing itself"
+                );
+            }
+            active
+        });
+    }
+}
+
+/// Joins `config.gossip_group` with the worker and data center IDs in `config`, and starts
+/// announcing them in the background.
+///
+/// This must be called from within a tokio runtime.
+pub(crate) fn join(config: &WorkerConfig) -> Result<Arc<Gossip>, WorkerError> {
+    let group = config
+        .gossip_group
+        .expect("gossip can only be joined with a GOSSIP_GROUP");
+    let socket = bind(group, config.gossip_interface)
+        .map_err(|source| WorkerError::Gossip { group, source })?;
+
+    let unix_ms = |time: SystemTime| {
+        time.duration_since(UNIX_EPOCH)
+            .map(|since| since.as_millis() as u64)
+            .unwrap_or(0)
+    };
+    let gossip = Arc::new(Gossip {
+        announcement: Announcement {
+            instance: RandomState::new().hash_one(std::process::id()),
+            data_center_id: config.data_center_id,
+            worker_id: config.worker_id,
+            epoch_ms: unix_ms(config.epoch),
+            started_at_ms: unix_ms(SystemTime::now()),
+        },
+        expire_after: config.gossip_interval * MISSED_ANNOUNCEMENTS,
+        conflicts: Mutex::new(HashMap::new()),
+    });
+    tokio::spawn(announce(
+        Arc::downgrade(&gossip),
+        socket,
+        group,
+        config.gossip_interval,
+    ));
+    tracing::info!(%group, "joined gossip group");
+    Ok(gossip)
+}
+
+fn bind(group: SocketAddrV4, interface: Ipv4Addr) -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    // NOTE(ayubun): every worker on the host listens on the group's port, so it has to be shared
+    socket.set_reuse_address(true)?;
+    socket.bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, group.port()).into())?;
+    socket.join_multicast_v4(group.ip(), &interface)?;
+    socket.set_multicast_if_v4(&interface)?;
+    // NOTE(ayubun): without this, workers on the same host would never hear each other
+    socket.set_multicast_loop_v4(true)?;
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket.into())
+}
+
+/// Announces `gossip` every `interval` and listens to everyone else's announcements, for as long
+/// as anything still holds onto it.
+async fn announce(
+    gossip: Weak<Gossip>,
+    socket: UdpSocket,
+    group: SocketAddrV4,
+    interval: Duration,
+) {
+    let Some(payload) = gossip
+        .upgrade()
+        .and_then(|gossip| serde_json::to_vec(&gossip.announcement).ok())
+    else {
+        return;
+    };
+    let mut ticker = tokio::time::interval(interval);
+    let mut buffer = [0; 1024];
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                let Some(gossip) = gossip.upgrade() else {
+                    return;
+                };
+                gossip.expire();
+                if let Err(err) = socket.send_to(&payload, group).await {
+                    tracing::warn!(%group, error = %err, "failed to send gossip announcement");
+                }
+            }
+            received = socket.recv_from(&mut buffer) => {
+                let Some(gossip) = gossip.upgrade() else {
+                    return;
+                };
+                match received {
+                    Ok((len, address)) => gossip.observe(&buffer[..len], address),
+                    Err(err) => tracing::warn!(%group, error = %err, "failed to receive gossip announcement"),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{health, Worker};
+    use std::net::UdpSocket as StdUdpSocket;
+
+    const INTERVAL: Duration = Duration::from_millis(20);
+
+    /// A multicast group on a port that nothing else is using.
+    fn free_group(last_octet: u8) -> SocketAddrV4 {
+        let port = StdUdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        SocketAddrV4::new(Ipv4Addr::new(239, 255, 77, last_octet), port)
+    }
+
+    fn gossiping_worker(group: SocketAddrV4, worker_id: u64, epoch_ms: u64) -> Worker {
+        let config = WorkerConfig::builder()
+            .worker_id(worker_id)
+            .epoch(UNIX_EPOCH + Duration::from_millis(epoch_ms))
+            .gossip_group(group)
+            .gossip_interface(Ipv4Addr::LOCALHOST)
+            .gossip_interval(INTERVAL)
+            .build()
+            .unwrap();
+        let gossip = join(&config).unwrap();
+        Worker::new(config).with_gossip(gossip)
+    }
+
+    fn is_ready(worker: &Worker) -> bool {
+        health::reply(health::readiness(worker), false)
+            .status()
+            .is_success()
+    }
+
+    /// Waits (for up to a second) until `condition` holds.
+    async fn eventually(condition: impl Fn() -> bool) -> bool {
+        for _ in 0..100 {
+            if condition() {
+                return true;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        false
+    }
+
+    #[tokio::test]
+    async fn test_workers_with_the_same_worker_id_become_unready() {
+        let group = free_group(1);
+        let first = gossiping_worker(group, 1, 0);
+        let duplicate = gossiping_worker(group, 1, 0);
+        let other = gossiping_worker(group, 2, 0);
+
+        assert!(
+            eventually(|| !is_ready(&first) && !is_ready(&duplicate)).await,
+            "workers with the same worker ID should notice each other"
+        );
+        let conflict = first.gossip().unwrap().conflict().unwrap();
+        assert!(conflict.contains("the same worker ID"), "{conflict}");
+        // NOTE(ayubun): by now, the other worker has heard several announcements from both
+        assert!(is_ready(&other));
+
+        // NOTE(ayubun): once the duplicate goes away, the first worker is ready again
+        drop(duplicate);
+        assert!(
+            eventually(|| is_ready(&first)).await,
+            "the first worker should recover once the duplicate is gone"
+        );
+        assert!(is_ready(&other));
+    }
+
+    #[tokio::test]
+    async fn test_workers_with_different_epochs_become_unready() {
+        let group = free_group(2);
+        let first = gossiping_worker(group, 1, 0);
+        let second = gossiping_worker(group, 2, 1420070400000);
+
+        assert!(
+            eventually(|| !is_ready(&first) && !is_ready(&second)).await,
+            "workers with different epochs should notice each other"
+        );
+        let conflict = second.gossip().unwrap().conflict().unwrap();
+        assert!(conflict.contains("a different EPOCH"), "{conflict}");
+    }
+
+    #[test]
+    fn test_own_announcements_never_conflict() {
+        let announcement = Announcement {
+            instance: 7,
+            data_center_id: 0,
+            worker_id: 1,
+            epoch_ms: 0,
+            started_at_ms: 0,
+        };
+        assert_eq!(announcement.conflict_with(&announcement), None);
+        let peer = Announcement {
+            instance: 8,
+            worker_id: 2,
+            ..announcement
+        };
+        assert_eq!(announcement.conflict_with(&peer), None);
+        let peer = Announcement {
+            data_center_id: 1,
+            worker_id: 1,
+            ..peer
+        };
+        assert_eq!(announcement.conflict_with(&peer), None);
+    }
+}
@@ -0,0 +1,322 @@
+//! A gRPC `SnowflakeService`, served on `GRPC_PORT` alongside the HTTP API.
+//!
+//! The service shares its [`Worker`] with the HTTP routes, so IDs stay unique across both. The
+//! generated client lives in [`proto::snowflake_service_client`].
+
+use std::{future::Future, pin::Pin};
+
+use tokio::net::TcpListener;
+use tokio_stream::{wrappers::TcpListenerStream, Stream, StreamExt};
+use tonic::{Request, Response, Status};
+
+use crate::{GenerateError, Worker, GENERATE_STREAM_CHUNK_SIZE};
+
+use proto::{
+    snowflake_service_server::{self, SnowflakeServiceServer},
+    DecodeRequest, DecodeResponse, DecodedId, GenerateRequest, GenerateResponse,
+};
+
+/// The messages, client and server generated from `proto/snowflake.proto`.
+pub mod proto {
+    tonic::include_proto!("snowflake.v1");
+}
+
+/// Implements the `SnowflakeService` RPCs on top of a [`Worker`].
+#[derive(Clone)]
+pub struct SnowflakeService {
+    worker: Worker,
+}
+
+impl SnowflakeService {
+    pub fn new(worker: Worker) -> SnowflakeService {
+        SnowflakeService { worker }
+    }
+
+    /// Wraps the service in a tonic server, ready to be added to a [`tonic::transport::Server`].
+    pub fn into_server(self) -> SnowflakeServiceServer<SnowflakeService> {
+        SnowflakeServiceServer::new(self)
+    }
+}
+
+// NOTE(ayubun): proto3 can't tell an unset count apart from 0, so 0 means "the default" (1 ID),
+// just like leaving out `count` in the HTTP API does
+#[allow(clippy::result_large_err)]
+fn requested_count(
+    worker: &Worker,
+    request: &GenerateRequest,
+    stream: bool,
+) -> Result<u64, Status> {
+    check_started(worker)?;
+    let (max_count, variable) = if stream {
+        (worker.config().max_stream_size, "MAX_STREAM_SIZE")
+    } else {
+        (worker.config().max_batch_size, "MAX_BATCH_SIZE")
+    };
+    if request.count > max_count {
+        return Err(Status::invalid_argument(format!(
+            "Invalid count: must be at most {max_count} ({variable})"
+        )));
+    }
+    Ok(request.count.max(1))
+}
+
+// NOTE(ayubun): until the clock has passed the persisted high-water mark, any ID we hand out
+// could be a duplicate of one from the previous run
+#[allow(clippy::result_large_err)]
+fn check_started(worker: &Worker) -> Result<(), Status> {
+    if worker.is_starting() {
+        return Err(Status::unavailable(
+            "Waiting for the clock to pass the high-water mark in STATE_FILE",
+        ));
+    }
+    Ok(())
+}
+
+fn unavailable(err: GenerateError) -> Status {
+    Status::unavailable(err.to_string())
+}
+
+#[tonic::async_trait]
+impl snowflake_service_server::SnowflakeService for SnowflakeService {
+    async fn generate(
+        &self,
+        request: Request<GenerateRequest>,
+    ) -> Result<Response<GenerateResponse>, Status> {
+        let count = requested_count(&self.worker, request.get_ref(), false)?;
+        let ids = self
+            .worker
+            .generate_batch_async(count as usize)
+            .await
+            .map_err(unavailable)?;
+        Ok(Response::new(GenerateResponse { ids }))
+    }
+
+    type GenerateStreamStream =
+        Pin<Box<dyn Stream<Item = Result<GenerateResponse, Status>> + Send + 'static>>;
+
+    // NOTE(ayubun): tonic decides the item type of the stream, so there's no way to box `Status`
+    #[allow(clippy::result_large_err)]
+    async fn generate_stream(
+        &self,
+        request: Request<GenerateRequest>,
+    ) -> Result<Response<Self::GenerateStreamStream>, Status> {
+        // NOTE(ayubun): each chunk is only generated once the client is ready for it, so large
+        // counts never have to be held in memory all at once (and are capped by MAX_STREAM_SIZE
+        // instead of MAX_BATCH_SIZE, just like streamed HTTP responses)
+        let count = requested_count(&self.worker, request.get_ref(), true)?;
+        let chunks = self
+            .worker
+            .generate_chunks(count, GENERATE_STREAM_CHUNK_SIZE)
+            .map(|ids| ids.map(|ids| GenerateResponse { ids }).map_err(unavailable));
+        Ok(Response::new(Box::pin(chunks)))
+    }
+
+    async fn decode(
+        &self,
+        request: Request<DecodeRequest>,
+    ) -> Result<Response<DecodeResponse>, Status> {
+        let request = request.into_inner();
+        if request.ids.is_empty() {
+            return Err(Status::invalid_argument(
+                "Invalid ids: must be a non-empty list",
+            ));
+        }
+        if let Some(id) = request.ids.iter().find(|&&id| id < 0) {
+            return Err(Status::invalid_argument(format!(
+                "Invalid id: {id} is not a valid snowflake ID"
+            )));
+        }
+
+        let ids = request
+            .ids
+            .into_iter()
+            .map(|id| {
+                self.worker.decode(id).map(|decoded| DecodedId {
+                    id: decoded.id,
+                    timestamp_ms: decoded.timestamp_ms,
+                    timestamp_utc: decoded.timestamp_utc,
+                    data_center_id: decoded.data_center_id,
+                    worker_id: decoded.worker_id,
+                    sequence: decoded.sequence,
+                })
+            })
+            .collect::<Result<_, _>>()
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
+        Ok(Response::new(DecodeResponse { ids }))
+    }
+}
+
+/// Serves the `SnowflakeService` on an already bound listener until `shutdown` resolves (and
+/// in-flight calls have finished), or until the server fails.
+pub(crate) async fn serve(
+    worker: Worker,
+    listener: TcpListener,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), tonic::transport::Error> {
+    tonic::transport::Server::builder()
+        .add_service(SnowflakeService::new(worker).into_server())
+        .serve_with_incoming_shutdown(TcpListenerStream::new(listener), shutdown)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BitLayout, WorkerConfig};
+    use proto::snowflake_service_client::SnowflakeServiceClient;
+    use snowflake_service_server::SnowflakeService as _;
+    use std::collections::HashSet;
+
+    fn service() -> SnowflakeService {
+        let config = WorkerConfig::builder()
+            .worker_id(7)
+            .data_center_id(3)
+            .build()
+            .unwrap();
+        SnowflakeService::new(Worker::new(config))
+    }
+
+    #[tokio::test]
+    async fn test_generate() {
+        let service = service();
+
+        let response = service
+            .generate(Request::new(GenerateRequest { count: 0 }))
+            .await
+            .unwrap();
+        assert_eq!(response.get_ref().ids.len(), 1, "Unset count should mean 1");
+
+        let response = service
+            .generate(Request::new(GenerateRequest { count: 5000 }))
+            .await
+            .unwrap();
+        let ids = &response.get_ref().ids;
+        let unique_ids: HashSet<i64> = ids.iter().cloned().collect();
+        assert_eq!(unique_ids.len(), 5000, "All IDs should be unique");
+
+        let status = service
+            .generate(Request::new(GenerateRequest { count: 100_001 }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_generate_stream_is_chunked() {
+        let service = service();
+
+        let response = service
+            .generate_stream(Request::new(GenerateRequest { count: 2500 }))
+            .await
+            .unwrap();
+        let chunks: Vec<GenerateResponse> = response
+            .into_inner()
+            .map(|chunk| chunk.unwrap())
+            .collect()
+            .await;
+
+        let chunk_sizes: Vec<usize> = chunks.iter().map(|chunk| chunk.ids.len()).collect();
+        assert_eq!(chunk_sizes, vec![1000, 1000, 500]);
+        let unique_ids: HashSet<i64> = chunks.into_iter().flat_map(|chunk| chunk.ids).collect();
+        assert_eq!(unique_ids.len(), 2500, "All IDs should be unique");
+
+        let status = service
+            .generate_stream(Request::new(GenerateRequest { count: 10_000_001 }))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert_eq!(
+            status.message(),
+            "Invalid count: must be at most 10000000 (MAX_STREAM_SIZE)"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_decode() {
+        let service = service();
+        let id = service.worker.generate().unwrap();
+
+        let response = service
+            .decode(Request::new(DecodeRequest { ids: vec![id] }))
+            .await
+            .unwrap();
+        let decoded = &response.get_ref().ids[0];
+        assert_eq!(decoded.id, id);
+        assert_eq!(decoded.worker_id, 7);
+        assert_eq!(decoded.data_center_id, 3);
+
+        let status = service
+            .decode(Request::new(DecodeRequest { ids: vec![] }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        let status = service
+            .decode(Request::new(DecodeRequest { ids: vec![-1] }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_decode_rejects_timestamps_past_9999() {
+        let config = WorkerConfig::builder()
+            .layout(BitLayout {
+                timestamp_bits: 53,
+                data_center_id_bits: 2,
+                worker_id_bits: 2,
+                sequence_bits: 6,
+            })
+            .build()
+            .unwrap();
+        let service = SnowflakeService::new(Worker::new(config));
+
+        let status = service
+            .decode(Request::new(DecodeRequest {
+                ids: vec![i64::MAX],
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_grpc_shares_generator_with_http() {
+        let service = service();
+        let routes = crate::routes(service.worker.clone());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(
+            service.worker.clone(),
+            listener,
+            std::future::pending(),
+        ));
+
+        let mut client = SnowflakeServiceClient::connect(format!("http://{addr}"))
+            .await
+            .unwrap();
+        let grpc_ids = client
+            .generate(GenerateRequest { count: 1000 })
+            .await
+            .unwrap()
+            .into_inner()
+            .ids;
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/generate")
+            .json(&serde_json::json!({"count": 1000}))
+            .reply(&routes)
+            .await;
+        let http_ids: Vec<i64> = serde_json::from_slice(resp.body()).unwrap();
+
+        let unique_ids: HashSet<i64> = grpc_ids.into_iter().chain(http_ids).collect();
+        assert_eq!(
+            unique_ids.len(),
+            2000,
+            "IDs should be unique across gRPC and HTTP"
+        );
+    }
+}
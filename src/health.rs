@@ -0,0 +1,160 @@
+//! The checks behind the `/livez`, `/readyz` and `/startupz` probes (and the older `/health`).
+
+use warp::Reply;
+
+use crate::{
+    problem::{Problem, ProblemType},
+    ClockRegressionPolicy, Worker,
+};
+
+/// The outcome of a single check. `detail` explains why a check failed.
+pub(crate) struct Check {
+    name: &'static str,
+    detail: Option<String>,
+}
+
+impl Check {
+    fn new(name: &'static str, detail: Option<String>) -> Check {
+        Check { name, detail }
+    }
+
+    fn passed(&self) -> bool {
+        self.detail.is_none()
+    }
+}
+
+/// Fails while the worker waits for the clock to pass the high-water mark in `STATE_FILE`.
+fn startup_check(worker: &Worker) -> Check {
+    Check::new(
+        "startup",
+        worker
+            .is_starting()
+            .then(|| "waiting for the clock to pass the high-water mark in STATE_FILE".to_string()),
+    )
+}
+
+/// Fails while the worker drains before shutting down.
+fn shutdown_check(worker: &Worker) -> Check {
+    Check::new(
+        "shutdown",
+        worker
+            .is_shutting_down()
+            .then(|| "draining before shutting down".to_string()),
+    )
+}
+
+/// Fails while the clock is behind the last issued ID, unless the worker is allowed to hold onto
+/// the last timestamp (in which case it can still serve IDs).
+fn clock_check(worker: &Worker) -> Check {
+    let hold = worker.config().clock_regression_policy == ClockRegressionPolicy::Hold;
+    Check::new(
+        "clock",
+        worker
+            .clock_monitor()
+            .current_drift_ms()
+            .filter(|_| !hold)
+            .map(|drift_ms| format!("clock is {drift_ms}ms behind the last issued ID")),
+    )
+}
+
+/// Fails while the worker can't be sure that nobody else holds its leased worker ID. With
+/// `permanent`, only fails once the lease is gone for good, since a restart is the only way to get
+/// a new one.
+fn lease_check(worker: &Worker, permanent: bool) -> Check {
+    let detail = worker.lease().and_then(|lease| {
+        let identity = lease.identity();
+        if lease.is_lost() {
+            Some(format!(
+                "lease on worker ID {} was lost",
+                identity.worker_id
+            ))
+        } else if !permanent && !lease.is_valid() {
+            Some(format!(
+                "lease on worker ID {} expired before it could be renewed",
+                identity.worker_id
+            ))
+        } else {
+            None
+        }
+    });
+    Check::new("lease", detail)
+}
+
+/// Fails while another worker on `GOSSIP_GROUP` announces the same identity (or a different
+/// `EPOCH`).
+fn peers_check(worker: &Worker) -> Check {
+    Check::new(
+        "peers",
+        worker.gossip().and_then(|gossip| gossip.conflict()),
+    )
+}
+
+/// The process is up and serving requests, so the only thing to check is whether it can ever
+/// generate IDs again.
+pub(crate) fn liveness(worker: &Worker) -> Vec<Check> {
+    vec![lease_check(worker, true)]
+}
+
+pub(crate) fn readiness(worker: &Worker) -> Vec<Check> {
+    vec![
+        startup_check(worker),
+        shutdown_check(worker),
+        clock_check(worker),
+        lease_check(worker, false),
+        peers_check(worker),
+    ]
+}
+
+pub(crate) fn startup(worker: &Worker) -> Vec<Check> {
+    vec![startup_check(worker)]
+}
+
+/// Whether a probe's query string asks for the verbose (JSON) response, i.e. `/readyz?verbose`.
+pub(crate) fn is_verbose(query: &str) -> bool {
+    query.split('&').any(|param| {
+        let (key, value) = param.split_once('=').unwrap_or((param, ""));
+        key == "verbose" && value != "false" && value != "0"
+    })
+}
+
+/// Answers a probe with `200 OK` if every check passed, or with a `503` problem listing the ones
+/// that failed. Verbose responses also include the status of every check.
+pub(crate) fn reply(checks: Vec<Check>, verbose: bool) -> warp::reply::Response {
+    let failed: Vec<&Check> = checks.iter().filter(|check| !check.passed()).collect();
+    let summary = || {
+        let checks: serde_json::Map<String, serde_json::Value> = checks
+            .iter()
+            .map(|check| {
+                let status = serde_json::json!({
+                    "status": if check.passed() { "ok" } else { "failed" },
+                    "detail": check.detail,
+                });
+                (check.name.to_string(), status)
+            })
+            .collect();
+        serde_json::Value::Object(checks)
+    };
+
+    if failed.is_empty() {
+        return if verbose {
+            warp::reply::json(&serde_json::json!({
+                "status": "ok",
+                "checks": summary(),
+            }))
+            .into_response()
+        } else {
+            "OK".into_response()
+        };
+    }
+
+    let detail = failed
+        .iter()
+        .map(|check| format!("{}: {}", check.name, check.detail.as_deref().unwrap_or("")))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mut problem = Problem::new(ProblemType::NotReady, detail);
+    if verbose {
+        problem = problem.with_extension("checks", summary());
+    }
+    problem.into_response()
+}
@@ -1,43 +1,556 @@
-use clap::Parser;
-use snowflake::SnowflakeIdGenerator;
-use std::{
-    env,
-    sync::{Arc, Mutex},
-    time::{Duration, SystemTime, UNIX_EPOCH},
-};
-use warp::Filter;
-
-const MAX_DATA_CENTER_ID: u8 = (1 << 5) - 1;
-const MAX_WORKER_ID: u8 = (1 << 5) - 1;
-
-const DEFAULT_EPOCH: SystemTime = UNIX_EPOCH;
+pub use allocator::{AllocatorError, Claim, Identity, RedisAllocator, WorkerIdAllocator};
+pub use clock::{ClockRegressionError, ClockRegressionPolicy};
+pub use config::{WorkerConfig, WorkerConfigBuilder};
+pub use error::{ConfigError, WorkerError};
+pub use generator::{BitLayout, GeneratorKind};
+pub use logging::LogFormat;
+pub use state::HighWaterMarkPolicy;
+pub use worker::{DecodeError, DecodedId, GenerateError, Worker};
+
+use problem::{Problem, ProblemType};
+use state::StateFile;
+use std::{future::Future, sync::Arc};
+use tokio_stream::StreamExt;
+use warp::{Filter, Reply};
+
+mod allocator;
+mod atomic_generator;
+mod clock;
+mod config;
+mod error;
+mod generator;
+mod gossip;
+pub mod grpc;
+mod health;
+mod lockfile;
+mod logging;
+mod metrics;
+mod problem;
+mod state;
+mod worker;
 
 #[derive(serde::Deserialize)]
 struct GenerateRequest {
     count: Option<i64>,
+    id_format: Option<String>,
+    format: Option<String>,
+    stream: Option<bool>,
+}
+
+/// How `/generate` writes each ID in a JSON (or NDJSON) response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum IdFormat {
+    /// `[123,456]`
+    #[default]
+    Number,
+    /// `["123","456"]`, which is safe for clients that parse JSON numbers as doubles (i.e.
+    /// JavaScript), since IDs can be larger than 2^53
+    String,
+}
+
+impl IdFormat {
+    fn parse(value: &str) -> Option<IdFormat> {
+        match value {
+            "number" => Some(IdFormat::Number),
+            "string" => Some(IdFormat::String),
+            _ => None,
+        }
+    }
+}
+
+/// How `/generate` lays out its response, picked with the `format` field or the `Accept` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum ResponseFormat {
+    /// A JSON array, i.e. `[123,456]`
+    #[default]
+    Json,
+    /// One JSON value per line, i.e. `123\n456\n`
+    Ndjson,
+    /// One bare ID per line, for shell scripts and bulk loaders
+    Text,
+    /// Raw 8-byte big-endian IDs, back to back, for clients that care about throughput
+    Binary,
+}
+
+impl ResponseFormat {
+    fn parse(value: &str) -> Option<ResponseFormat> {
+        match value {
+            "json" => Some(ResponseFormat::Json),
+            "ndjson" => Some(ResponseFormat::Ndjson),
+            "text" => Some(ResponseFormat::Text),
+            "binary" => Some(ResponseFormat::Binary),
+            _ => None,
+        }
+    }
+
+    /// Picks the format with the highest `q` weight in an `Accept` header. A media type's own
+    /// weight wins over a `type/*` one, which wins over `*/*`, and a weight of `0` rules the format
+    /// out. Ties go to whichever was listed first, and if nothing we know how to write is
+    /// acceptable, we fall back to JSON anyway.
+    fn from_accept(accept: &str) -> ResponseFormat {
+        const MEDIA_TYPES: [(ResponseFormat, &str); 4] = [
+            (ResponseFormat::Json, "application/json"),
+            (ResponseFormat::Ndjson, "application/x-ndjson"),
+            (ResponseFormat::Text, "text/plain"),
+            (ResponseFormat::Binary, "application/octet-stream"),
+        ];
+        let media_ranges: Vec<(&str, f32)> = accept
+            .split(',')
+            .filter_map(|media_range| {
+                let mut params = media_range.split(';');
+                let media_range = params.next()?.trim();
+                // NOTE(ayubun): a weight we can't parse makes the whole media range invalid,
+                // rather than silently treating it as 1
+                let weight = match params.find_map(|param| {
+                    let (name, value) = param.split_once('=')?;
+                    name.trim().eq_ignore_ascii_case("q").then_some(value)
+                }) {
+                    Some(weight) => weight.trim().parse().ok()?,
+                    None => 1.0,
+                };
+                Some((media_range, weight))
+            })
+            .collect();
+        let find = |media_range: &str| {
+            media_ranges
+                .iter()
+                .position(|(candidate, _)| candidate.eq_ignore_ascii_case(media_range))
+        };
+
+        MEDIA_TYPES
+            .into_iter()
+            .filter_map(|(format, media_type)| {
+                let (kind, _) = media_type.split_once('/')?;
+                let position = find(media_type)
+                    .or_else(|| find(&format!("{kind}/*")))
+                    .or_else(|| find("*/*"))?;
+                let weight = media_ranges[position].1;
+                (weight > 0.0).then_some((format, weight, position))
+            })
+            .min_by(|(_, a_weight, a_position), (_, b_weight, b_position)| {
+                b_weight
+                    .total_cmp(a_weight)
+                    .then(a_position.cmp(b_position))
+            })
+            .map(|(format, ..)| format)
+            .unwrap_or_default()
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            ResponseFormat::Json => "application/json",
+            ResponseFormat::Ndjson => "application/x-ndjson",
+            ResponseFormat::Text => "text/plain; charset=utf-8",
+            ResponseFormat::Binary => "application/octet-stream",
+        }
+    }
+
+    /// What goes before the first ID, between chunks of IDs, and after the last ID.
+    fn delimiters(self) -> (&'static str, &'static str, &'static str) {
+        match self {
+            ResponseFormat::Json => ("[", ",", "]"),
+            ResponseFormat::Ndjson | ResponseFormat::Text | ResponseFormat::Binary => ("", "", ""),
+        }
+    }
+}
+
+/// Tells clients how many IDs are in a `/generate` response, so that binary responses can be
+/// read (and checked) without a length prefix in the body.
+const ID_COUNT_HEADER: &str = "x-id-count";
+
+fn render_ids(ids: Vec<i64>, format: ResponseFormat, id_format: IdFormat) -> Vec<u8> {
+    let (open, _, close) = format.delimiters();
+    let mut buffer = open.as_bytes().to_vec();
+    render_id_list(&mut buffer, ids, format, id_format);
+    buffer.extend_from_slice(close.as_bytes());
+    buffer
+}
+
+/// Renders IDs into `buffer` without the delimiters around them, so that chunks of IDs can be
+/// written one after the other.
+fn render_id_list(
+    buffer: &mut Vec<u8>,
+    ids: Vec<i64>,
+    format: ResponseFormat,
+    id_format: IdFormat,
+) {
+    use std::io::Write;
+
+    if format == ResponseFormat::Binary {
+        buffer.reserve(ids.len() * 8);
+        for id in ids {
+            buffer.extend_from_slice(&id.to_be_bytes());
+        }
+        return;
+    }
+
+    // NOTE(ayubun): plain text has no quotes to speak of, so `id_format` only applies to JSON
+    let quoted = format != ResponseFormat::Text && id_format == IdFormat::String;
+    for (i, id) in ids.into_iter().enumerate() {
+        if format == ResponseFormat::Json && i > 0 {
+            buffer.push(b',');
+        }
+        let _ = if quoted {
+            write!(buffer, "\"{id}\"")
+        } else {
+            write!(buffer, "{id}")
+        };
+        if format != ResponseFormat::Json {
+            buffer.push(b'\n');
+        }
+    }
+}
+
+/// Extracts the `Accept` header, if there is one that can be read.
+fn accept_header(
+) -> impl Filter<Extract = (Option<String>,), Error = std::convert::Infallible> + Clone {
+    // NOTE(ayubun): header::optional rejects a header that isn't valid UTF-8 (which would end up as
+    // a 500), so an unreadable one is treated as if it wasn't sent at all, i.e. plain JSON
+    warp::header::headers_cloned().map(|headers: warp::http::HeaderMap| {
+        headers
+            .get(warp::http::header::ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .map(String::from)
+    })
+}
+
+/// The largest request body that `POST /generate` and `POST /decode` accept, which leaves plenty
+/// of room for a few thousand IDs to decode.
+const MAX_BODY_SIZE: u64 = 64 * 1024;
+
+/// Rejects request bodies larger than [`MAX_BODY_SIZE`] before they're read.
+fn body_size_limit() -> impl Filter<Extract = (), Error = warp::Rejection> + Copy {
+    // NOTE(ayubun): content_length_limit on its own rejects requests without a content-length,
+    // which an empty `POST /generate` (i.e. `curl -X POST`) doesn't send. chunked bodies still
+    // need one though, since there'd be no way to cap them before reading them in full
+    let no_body = warp::header::optional::<String>("content-length")
+        .and(warp::header::optional::<String>("transfer-encoding"))
+        .and_then(
+            |length: Option<String>, encoding: Option<String>| async move {
+                match (length, encoding) {
+                    (None, None) => Ok(()),
+                    _ => Err(warp::reject()),
+                }
+            },
+        )
+        .untuple_one();
+    warp::body::content_length_limit(MAX_BODY_SIZE)
+        .or(no_body)
+        .unify()
+}
+
+/// How many IDs are generated at a time for streamed responses (and gRPC `GenerateStream`).
+const GENERATE_STREAM_CHUNK_SIZE: u64 = 1000;
+
+/// Streams `count` IDs back over a chunked response, generating each chunk only once hyper is
+/// ready to write it.
+async fn stream_ids(
+    worker: &Worker,
+    count: u64,
+    format: ResponseFormat,
+    id_format: IdFormat,
+) -> Result<warp::hyper::Body, GenerateError> {
+    let (open, separator, close) = format.delimiters();
+    let mut chunks = Box::pin(worker.generate_chunks(count, GENERATE_STREAM_CHUNK_SIZE));
+    // NOTE(ayubun): the first chunk is generated up front, so that a clock regression (or a lost
+    // lease) can still be answered with a 503. after that the status has already been sent, so the best we can do is
+    // to cut the response off, which clients will see as a truncated body
+    let first = chunks.next().await.expect("count is positive")?;
+    let mut buffer = open.as_bytes().to_vec();
+    render_id_list(&mut buffer, first, format, id_format);
+    let rest = chunks.map(move |ids| match ids {
+        Ok(ids) => {
+            let mut buffer = separator.as_bytes().to_vec();
+            render_id_list(&mut buffer, ids, format, id_format);
+            Ok(buffer)
+        }
+        Err(err) => {
+            tracing::warn!(error = %err, "aborting streamed response");
+            Err(err)
+        }
+    });
+    let body = tokio_stream::once(Ok(buffer))
+        .chain(rest)
+        .chain(tokio_stream::once(Ok(close.as_bytes().to_vec())));
+    Ok(warp::hyper::Body::wrap_stream(body))
+}
+
+/// Validates a `/generate` request (from either the POST body or the GET query string), and
+/// generates the IDs it asks for.
+async fn generate_reply(
+    worker: &Worker,
+    accept: Option<String>,
+    request: Option<GenerateRequest>,
+) -> warp::reply::Response {
+    let (count, id_format, format, stream) = match request {
+        Some(request) => (
+            request.count,
+            request.id_format,
+            request.format,
+            request.stream,
+        ),
+        None => (None, None, None, None),
+    };
+    // NOTE(ayubun): until the clock has passed the persisted high-water mark, any ID we hand out
+    // could be a duplicate of one from the previous run
+    if worker.is_starting() {
+        return Problem::new(
+            ProblemType::Starting,
+            "Waiting for the clock to pass the high-water mark in STATE_FILE",
+        )
+        .into_response();
+    }
+    let count = count.unwrap_or(1);
+    let stream = stream.unwrap_or(false);
+    tracing::Span::current().record("count", count);
+    let id_format = match id_format.as_deref().map(IdFormat::parse) {
+        None => IdFormat::default(),
+        Some(Some(id_format)) => id_format,
+        Some(None) => {
+            return Problem::new(
+                ProblemType::InvalidField,
+                "Invalid id_format: must be \"number\" or \"string\"",
+            )
+            .with_field("id_format")
+            .into_response();
+        }
+    };
+    // NOTE(ayubun): an explicit `format` wins over the `Accept` header, since it's easier
+    // to get right from a shell script
+    let format = match format.as_deref().map(ResponseFormat::parse) {
+        None => accept
+            .as_deref()
+            .map(ResponseFormat::from_accept)
+            .unwrap_or_default(),
+        Some(Some(format)) => format,
+        Some(None) => {
+            return Problem::new(
+                ProblemType::InvalidField,
+                "Invalid format: must be \"json\", \"ndjson\", \"text\" or \"binary\"",
+            )
+            .with_field("format")
+            .into_response();
+        }
+    };
+
+    // NOTE(ayubun): We want to also return a 400 Bad Request for zero or negative count
+    // for similar reasons to the JSON parsing.
+    if count <= 0 {
+        return Problem::new(
+            ProblemType::InvalidField,
+            "Invalid count: must be a positive integer",
+        )
+        .with_field("count")
+        .into_response();
+    }
+    // NOTE(ayubun): every ID in a batch is held in memory until the response is written,
+    // so huge counts could OOM the worker (or hog the generator for seconds). streamed
+    // responses are generated a chunk at a time, so they get a much higher limit of their own
+    let (max_count, variable) = if stream {
+        (worker.config().max_stream_size, "MAX_STREAM_SIZE")
+    } else {
+        (worker.config().max_batch_size, "MAX_BATCH_SIZE")
+    };
+    if count as u64 > max_count {
+        return Problem::new(
+            ProblemType::BatchTooLarge,
+            format!("Invalid count: must be at most {max_count} ({variable})"),
+        )
+        .with_field("count")
+        .into_response();
+    }
+
+    let body = if stream {
+        stream_ids(worker, count as u64, format, id_format).await
+    } else {
+        worker
+            .generate_batch_async(count as usize)
+            .await
+            .map(|ids| render_ids(ids, format, id_format).into())
+    };
+    match body {
+        Ok(body) => warp::http::Response::builder()
+            .header("content-type", format.content_type())
+            .header(ID_COUNT_HEADER, count)
+            .body(body)
+            .expect("generate response is valid"),
+        Err(err @ GenerateError::ClockRegression(_)) => {
+            Problem::new(ProblemType::ClockRegression, err.to_string()).into_response()
+        }
+        Err(err @ GenerateError::LeaseLost) => {
+            Problem::new(ProblemType::LeaseLost, err.to_string()).into_response()
+        }
+        Err(err @ GenerateError::TimestampOverflow { .. }) => {
+            Problem::new(ProblemType::TimestampOverflow, err.to_string()).into_response()
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct DecodeRequest {
+    ids: Vec<i64>,
 }
 
-#[derive(Debug, clap::Parser)]
-struct Args {
-    #[arg(long, default_value = "8080", env = "PORT")]
-    port: u16,
+/// Configures the worker from CLI args and environment variables, then serves the HTTP API until
+/// SIGINT/SIGTERM is received (see [`exit_signal`]), at which point it shuts down gracefully.
+///
+/// Resolves with a [`WorkerError`] if the worker couldn't be started, or stopped unexpectedly.
+pub async fn run_worker() -> Result<(), WorkerError> {
+    run_worker_until(exit_signal()).await
+}
 
-    // TO SET WORKER ID AUTOMATICALLY IN A K8S STATEFUL SET, SET TO "FROM_HOSTNAME"
-    #[arg(long, default_value = "0", env = "WORKER_ID")]
-    worker_id: String,
+/// Like [`run_worker`], but shuts down gracefully once `signal` resolves instead.
+///
+/// Shutting down first fails `/health`, then keeps serving for `SHUTDOWN_DRAIN_MS` so that load
+/// balancers can stop routing requests to the worker, and finally waits for in-flight requests to
+/// finish before resolving.
+pub async fn run_worker_until(
+    signal: impl Future<Output = ()> + Send + 'static,
+) -> Result<(), WorkerError> {
+    let config = WorkerConfig::try_from_env()?;
+    logging::init(&config);
+
+    tracing::info!(
+        worker_id = config.worker_id,
+        data_center_id = config.data_center_id,
+        epoch_ms = config
+            .epoch
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0),
+        timestamp_bits = config.layout.timestamp_bits,
+        data_center_id_bits = config.layout.data_center_id_bits,
+        worker_id_bits = config.layout.worker_id_bits,
+        sequence_bits = config.layout.sequence_bits,
+        port = config.port,
+        grpc_port = ?config.grpc_port,
+        generator = ?config.generator_kind,
+        max_batch_size = config.max_batch_size,
+        max_stream_size = config.max_stream_size,
+        allow_get_generate = config.allow_get_generate,
+        clock_regression_policy = ?config.clock_regression_policy,
+        clock_regression_max_wait_ms = config.clock_regression_max_wait.as_millis() as u64,
+        state_file = ?config.state_file,
+        high_water_mark_policy = ?config.high_water_mark_policy,
+        shutdown_drain_ms = config.shutdown_drain.as_millis() as u64,
+        // NOTE(ayubun): the URL itself isn't logged, since it may contain a password
+        lease = config.lease_store.is_some(),
+        lease_ttl_ms = config.lease_ttl.as_millis() as u64,
+        lockfile_dir = ?config.lockfile_dir,
+        gossip_group = ?config.gossip_group,
+        "starting snowflake-id-worker"
+    );
+    let worker = match &config.lease_store {
+        Some(url) => {
+            // NOTE(ayubun): a request that takes longer than this would leave too little of the
+            // lease to renew it again before it expires
+            let allocator =
+                RedisAllocator::connect(url, &config.lease_key_prefix, config.lease_ttl / 3)
+                    .await
+                    .map_err(|err| WorkerError::LeaseStore {
+                        message: err.to_string(),
+                    })?;
+            Worker::with_allocator(config, Arc::new(allocator)).await?
+        }
+        // NOTE(ayubun): with WORKER_ID=FROM_LOCKFILE, this locks the worker ID slot, which the
+        // worker holds onto until it's dropped
+        None => Worker::try_new(config)?,
+    };
+    // NOTE(ayubun): this is joined after the worker ID is claimed, since that's what gets announced
+    let worker = match worker.config().gossip_group {
+        Some(_) => {
+            let gossip = gossip::join(worker.config())?;
+            worker.with_gossip(gossip)
+        }
+        None => worker,
+    };
 
-    #[arg(long, default_value = "0", env = "DATA_CENTER_ID")]
-    data_center_id: u8,
+    // NOTE(ayubun): the servers start before the high-water mark is restored, so that the probes
+    // can report the worker as starting (rather than not answering at all) while it waits
+    worker.set_starting(true);
+    let server = serve(worker.clone(), signal);
+    tokio::pin!(server);
+
+    // NOTE(ayubun): this is held for the lifetime of the worker, since dropping it writes the
+    // final high-water mark
+    let _state_file = match &worker.config().state_file {
+        Some(path) => {
+            let state_file = StateFile::open(path, worker.config(), worker.generator());
+            tokio::select! {
+                result = &mut server => return result,
+                state_file = state_file => Some(state_file?),
+            }
+        }
+        None => None,
+    };
+    worker.set_starting(false);
+    tracing::info!("ready to generate IDs");
 
-    #[arg(long, env = "EPOCH")]
-    epoch: Option<u64>,
+    let result = server.await;
+    worker.release_lease().await;
+    result
 }
 
-pub async fn run_worker() {
-    let args = Args::parse();
-    warp::serve(create_routes())
-        .run(([0, 0, 0, 0], args.port))
-        .await;
+/// Serves the HTTP (and optionally gRPC) APIs until `signal` resolves and the worker has drained.
+async fn serve(
+    worker: Worker,
+    signal: impl Future<Output = ()> + Send + 'static,
+) -> Result<(), WorkerError> {
+    let (drained_tx, drained_rx) = tokio::sync::watch::channel(false);
+    let drain_worker = worker.clone();
+    tokio::spawn(async move {
+        signal.await;
+        let drain = drain_worker.config().shutdown_drain;
+        tracing::info!(
+            drain_ms = drain.as_millis() as u64,
+            "shutting down, draining requests"
+        );
+        drain_worker.begin_shutdown();
+        tokio::time::sleep(drain).await;
+        tracing::info!("drained, waiting for in-flight requests to finish");
+        let _ = drained_tx.send(true);
+    });
+    let drained = move || {
+        let mut drained_rx = drained_rx.clone();
+        async move {
+            let _ = drained_rx.wait_for(|&drained| drained).await;
+        }
+    };
+
+    let port = worker.config().port;
+    let (_, server) = warp::serve(routes(worker.clone()))
+        .try_bind_with_graceful_shutdown(([0, 0, 0, 0], port), drained())
+        .map_err(|source| WorkerError::Bind { port, source })?;
+    tracing::info!(port, "serving HTTP");
+
+    let Some(grpc_port) = worker.config().grpc_port else {
+        server.await;
+        return Ok(());
+    };
+    let grpc_error = |source| WorkerError::Grpc {
+        port: grpc_port,
+        source,
+    };
+    let listener =
+        tokio::net::TcpListener::bind(std::net::SocketAddr::from(([0, 0, 0, 0], grpc_port)))
+            .await
+            .map_err(grpc_error)?;
+    tracing::info!(grpc_port, "serving gRPC");
+
+    // NOTE(ayubun): both servers stop on their own once the worker has drained, but the gRPC
+    // server could also fail before that, which should bring down the whole worker
+    let grpc_server = grpc::serve(worker, listener, drained());
+    tokio::pin!(server, grpc_server);
+    tokio::select! {
+        _ = &mut server => grpc_server.await,
+        result = &mut grpc_server => {
+            if result.is_ok() {
+                server.await;
+            }
+            result
+        }
+    }
+    .map_err(|err| grpc_error(std::io::Error::other(err)))
 }
 
 /// Returns a future which will resolve when Ctrl-C is received.
@@ -68,141 +581,317 @@ pub async fn exit_signal() {
     }
 }
 
+/// Creates the HTTP routes for a worker configured from CLI args and environment variables.
+///
+/// # Panics
+///
+/// Panics if the configuration is invalid. See [`try_create_routes`] for a fallible version.
 pub fn create_routes() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-    // NOTE(ayubun): I'm not certain if Arc<Mutex<SnowflakeIdGenerator>> is the best way to go
-    // about this, so if any onlookers have a more clever idea, please open a pull request or issue <3
-    let snowflake_generator: Arc<Mutex<SnowflakeIdGenerator>> =
-        Arc::new(Mutex::new(snowflake_id_generator_from_env()));
+    try_create_routes().unwrap_or_else(|err| panic!("{err}"))
+}
+
+/// Creates the HTTP routes for a worker configured from CLI args and environment variables,
+/// returning a [`ConfigError`] if the configuration is invalid, or if it needs a worker ID to be
+/// claimed first (see [`Worker::try_new`]).
+pub fn try_create_routes(
+) -> Result<impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone, ConfigError> {
+    Ok(routes(Worker::try_new(WorkerConfig::try_from_env()?)?))
+}
 
+/// Creates the HTTP routes for a worker with an explicit config, without reading CLI args or
+/// environment variables.
+///
+/// # Panics
+///
+/// Panics if `config` needs a worker ID to be claimed first (see [`Worker::try_new`]).
+pub fn create_routes_with(
+    config: WorkerConfig,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    routes(Worker::new(config))
+}
+
+fn routes(
+    worker: Worker,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     // Optional `GET /health` endpoint for health checks
-    let health_api = warp::path!("health").and(warp::get()).map(|| "OK");
+    let health_worker = worker.clone();
+    let health_api = warp::path!("health").and(warp::get()).map(move || {
+        let clock_monitor = health_worker.clock_monitor();
+        // NOTE(ayubun): while the clock is behind, the worker can't serve IDs unless it's
+        // allowed to hold onto the last timestamp, so it shouldn't report itself as healthy
+        let hold = health_worker.config().clock_regression_policy == ClockRegressionPolicy::Hold;
+        let response = match clock_monitor.current_drift_ms() {
+            // NOTE(ayubun): this comes first so that load balancers stop sending us requests
+            // while we drain, even if the clock happens to be fine
+            _ if health_worker.is_shutting_down() => {
+                Problem::new(ProblemType::ShuttingDown, "SHUTTING_DOWN").into_response()
+            }
+            _ if health_worker.is_starting() => {
+                Problem::new(ProblemType::Starting, "STARTING").into_response()
+            }
+            _ if health_worker.lease().is_some_and(|lease| !lease.is_valid()) => {
+                Problem::new(ProblemType::LeaseLost, "LEASE_LOST").into_response()
+            }
+            _ if health_worker
+                .gossip()
+                .is_some_and(|gossip| gossip.conflict().is_some()) =>
+            {
+                Problem::new(ProblemType::PeerConflict, "PEER_CONFLICT").into_response()
+            }
+            Some(drift_ms) => {
+                let detail =
+                    format!("CLOCK_REGRESSION: clock is {drift_ms}ms behind the last issued ID");
+                if hold {
+                    detail.into_response()
+                } else {
+                    Problem::new(ProblemType::ClockRegression, detail).into_response()
+                }
+            }
+            None => "OK".into_response(),
+        };
+        warp::reply::with_header(
+            response,
+            "x-clock-regression-events",
+            clock_monitor.events().to_string(),
+        )
+    });
+
+    // `GET /livez`, `GET /readyz` and `GET /startupz` probes, with `?verbose` for JSON
+    let probes = [
+        (
+            "livez",
+            health::liveness as fn(&Worker) -> Vec<health::Check>,
+        ),
+        ("readyz", health::readiness),
+        ("startupz", health::startup),
+    ]
+    .map(|(path, checks)| {
+        let probe_worker = worker.clone();
+        warp::path(path)
+            .and(warp::path::end())
+            .and(warp::get())
+            .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+            .map(move |query: String| {
+                health::reply(checks(&probe_worker), health::is_verbose(&query))
+            })
+            .boxed()
+    });
+    let [livez_api, readyz_api, startupz_api] = probes;
 
     // `POST /generate` endpoint ヽ(*・ω・)ﾉ
+    let generate_worker = worker.clone();
     let generate_api = warp::path!("generate")
         .and(warp::post())
+        .and(accept_header())
+        .and(body_size_limit())
         .and(warp::body::bytes())
-        .map(move |body: warp::hyper::body::Bytes| {
-            // NOTE(ayubun): We parse JSON manually to handle malformed JSON as a 400 Bad Request.
-            // This decision was made because the default behaviour is to silently fallback to the
-            // empty body route, which generates 1 ID. I feel like this isn't as ergonomic as the
-            // API telling you that you've made an error loudly so that you can fix it.
-            let request: Option<GenerateRequest> = if body.is_empty() {
-                None
+        .then(
+            move |accept: Option<String>, body: warp::hyper::body::Bytes| {
+                let worker = generate_worker.clone();
+                async move {
+                    // NOTE(ayubun): We parse JSON manually to handle malformed JSON as a 400 Bad Request.
+                    // This decision was made because the default behaviour is to silently fallback to the
+                    // empty body route, which generates 1 ID. I feel like this isn't as ergonomic as the
+                    // API telling you that you've made an error loudly so that you can fix it.
+                    let request: Option<GenerateRequest> = if body.is_empty() {
+                        None
+                    } else {
+                        match serde_json::from_slice(&body) {
+                            Ok(req) => Some(req),
+                            Err(err) => {
+                                return Problem::new(
+                                    ProblemType::InvalidJson,
+                                    format!("Invalid JSON format: {err}"),
+                                )
+                                .into_response();
+                            }
+                        }
+                    };
+                    generate_reply(&worker, accept, request).await
+                }
+            },
+        );
+
+    // Optional `GET /generate?count=N` endpoint, for clients that can only send GETs
+    let generate_get_worker = worker.clone();
+    let allow_get_generate = worker.config().allow_get_generate;
+    let generate_get_api = warp::path!("generate")
+        .and(warp::get())
+        .and_then(move || async move {
+            if allow_get_generate {
+                Ok(())
             } else {
-                match serde_json::from_slice(&body) {
-                    Ok(req) => Some(req),
-                    Err(_) => {
-                        return warp::reply::with_status(
-                            "Invalid JSON format".to_string(),
-                            warp::http::StatusCode::BAD_REQUEST,
-                        );
+                Err(warp::reject::not_found())
+            }
+        })
+        .untuple_one()
+        .and(accept_header())
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .then(move |accept: Option<String>, query: String| {
+            let worker = generate_get_worker.clone();
+            async move {
+                let request: GenerateRequest = match serde_urlencoded::from_str(&query) {
+                    Ok(req) => req,
+                    Err(err) => {
+                        return Problem::new(
+                            ProblemType::InvalidQuery,
+                            format!("Invalid query string: {err}"),
+                        )
+                        .into_response();
                     }
+                };
+                generate_reply(&worker, accept, Some(request)).await
+            }
+        })
+        // NOTE(ayubun): GETs are cacheable by default, and a cached ID is a duplicate ID
+        .with(warp::reply::with::header("cache-control", "no-store"));
+
+    // `POST /decode` endpoint, which breaks snowflake IDs back into their parts
+    let decode_worker = worker.clone();
+    let decode_api = warp::path!("decode")
+        .and(warp::post())
+        .and(body_size_limit())
+        .and(warp::body::bytes())
+        .map(move |body: warp::hyper::body::Bytes| {
+            let request: DecodeRequest = match serde_json::from_slice(&body) {
+                Ok(req) => req,
+                Err(err) => {
+                    return Problem::new(
+                        ProblemType::InvalidJson,
+                        format!("Invalid JSON format: expected {{\"ids\": [...]}} ({err})"),
+                    )
+                    .into_response();
                 }
             };
 
-            let count = request.and_then(|r| r.count).unwrap_or(1);
-
-            // NOTE(ayubun): We want to also return a 400 Bad Request for zero or negative count
-            // for similar reasons to the JSON parsing.
-            if count <= 0 {
-                return warp::reply::with_status(
-                    "Invalid count: must be a positive integer".to_string(),
-                    warp::http::StatusCode::BAD_REQUEST,
-                );
+            if request.ids.is_empty() {
+                return Problem::new(
+                    ProblemType::InvalidField,
+                    "Invalid ids: must be a non-empty list",
+                )
+                .with_field("ids")
+                .into_response();
             }
 
-            let mut ids: Vec<i64> = Vec::with_capacity(count as usize);
-            let mut unlocked_generator = snowflake_generator.lock().unwrap();
-            for _ in 0..count {
-                ids.push(unlocked_generator.real_time_generate());
+            // NOTE(ayubun): the top bit is never set on a generated ID, so negative IDs can't
+            // have come from this worker (or any other worker sharing the same layout)
+            if let Some(id) = request.ids.iter().find(|&&id| id < 0) {
+                return Problem::new(
+                    ProblemType::InvalidField,
+                    format!("Invalid id: {id} is not a valid snowflake ID"),
+                )
+                .with_field("ids")
+                .into_response();
             }
-            let response = format!(
-                "[{}]",
-                ids.into_iter()
-                    .map(|id| id.to_string())
-                    .collect::<Vec<_>>()
-                    .join(",")
-            );
-            warp::reply::with_status(response, warp::http::StatusCode::OK)
-        });
 
-    // TODO(ayubun): Add support for GRPC ? :3
-    generate_api.or(health_api)
-}
+            let decoded: Result<Vec<DecodedId>, DecodeError> = request
+                .ids
+                .into_iter()
+                .map(|id| decode_worker.decode(id))
+                .collect();
+            let decoded = match decoded {
+                Ok(decoded) => decoded,
+                Err(err) => {
+                    return Problem::new(ProblemType::InvalidField, err.to_string())
+                        .with_field("ids")
+                        .into_response();
+                }
+            };
+            let response = serde_json::to_string(&decoded).expect("decoded IDs are serializable");
+            warp::reply::with_status(response, warp::http::StatusCode::OK).into_response()
+        });
 
-fn snowflake_id_generator_from_env() -> SnowflakeIdGenerator {
-    let args = if cfg!(test) {
-        // NOTE(ayubun): during tests, we should only parse from environment variables.
-        // CLI args will conflict with the necessary `--test-threads=1` flag, which
-        // is needed to run tests in series so that the environment variables don't conflict
-        Args::try_parse_from([""]).unwrap_or_else(|_| Args::parse())
-    } else {
-        Args::parse()
-    };
+    // `GET /info` endpoint, which describes how this worker issues IDs
+    let info_worker = worker.clone();
+    let info_api = warp::path!("info").and(warp::get()).map(move || {
+        let config = info_worker.config();
+        warp::reply::json(&serde_json::json!({
+            "worker_id": config.worker_id,
+            "data_center_id": config.data_center_id,
+            "epoch_ms": config
+                .epoch
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+            "timestamp_bits": config.layout.timestamp_bits,
+            "data_center_id_bits": config.layout.data_center_id_bits,
+            "worker_id_bits": config.layout.worker_id_bits,
+            "sequence_bits": config.layout.sequence_bits,
+            "max_batch_size": config.max_batch_size,
+            "max_stream_size": config.max_stream_size,
+        }))
+    });
 
-    // NOTE(ayubun): for testing, i'm allowing hostname to be set via an environment variable.
-    // this is so we can ensure the hostname parsing works as expected~
-    let hostname = env::var("HOSTNAME_FOR_TESTING").unwrap_or_else(|_| {
-        hostname::get()
-            .map(|os| os.to_string_lossy().into_owned())
-            .unwrap_or_else(|_| "localhost".to_string())
+    // `GET /metrics` endpoint, in the Prometheus text format
+    let metrics_worker = worker.clone();
+    let metrics_api = warp::path!("metrics").and(warp::get()).map(move || {
+        warp::reply::with_header(
+            metrics_worker.metrics().render(),
+            "content-type",
+            metrics::CONTENT_TYPE,
+        )
     });
 
-    let epoch: SystemTime = args
-        .epoch
-        .map(|e| UNIX_EPOCH + Duration::from_millis(e))
-        .unwrap_or(DEFAULT_EPOCH);
-
-    let worker_id = if args.worker_id.eq_ignore_ascii_case("FROM_HOSTNAME") {
-        // NOTE(ayubun): assuming this is being run from a stateful set in k8s:
-        //
-        // snowflake-id-worker-0
-        // snowflake-id-worker-1
-        // ...
-        // snowflake-id-worker-n
-        //
-        // this code will try to grab the pod's index (n) and use it as the worker id
-        hostname
-            .rsplit_once('-')
-            .expect(
-                "cannot split WORKER_ID from hostname (WORKER_ID is being parsed from hostname)",
-            )
-            .1
-            .parse::<u8>()
-            .expect(
-                "cannot parse WORKER_ID from hostname (WORKER_ID is being parsed from hostname)",
+    generate_api
+        .or(generate_get_api)
+        .or(decode_api)
+        .or(health_api)
+        .or(livez_api)
+        .or(readyz_api)
+        .or(startupz_api)
+        .or(info_api)
+        .or(metrics_api)
+        .recover(problem::recover)
+        .with(warp::log::custom(move |info| {
+            worker
+                .metrics()
+                .http_requests
+                .with_label_values(&[info.status().as_str()])
+                .inc();
+
+            // NOTE(ayubun): this runs inside of the request span below, so the status and latency
+            // end up on the span (and on this event)
+            let span = tracing::Span::current();
+            span.record("status", info.status().as_u16());
+            span.record("latency_ms", info.elapsed().as_secs_f64() * 1000.0);
+            tracing::info!("finished request");
+        }))
+        .with(warp::trace(|info| {
+            tracing::info_span!(
+                "request",
+                method = %info.method(),
+                path = info.path(),
+                count = tracing::field::Empty,
+                status = tracing::field::Empty,
+                latency_ms = tracing::field::Empty,
             )
-    } else {
-        args.worker_id.parse::<u8>().unwrap_or_else(|_| {
-            panic!(
-                "cannot parse WORKER_ID as a valid u8 (WORKER_ID: \"{}\")",
-                args.worker_id
-            )
-        })
-    };
-
-    if args.data_center_id > MAX_DATA_CENTER_ID {
-        panic!("DATA_CENTER_ID must be less than {MAX_DATA_CENTER_ID}");
-    }
-
-    if worker_id > MAX_WORKER_ID {
-        panic!("WORKER_ID must be less than {MAX_WORKER_ID}");
-    }
-
-    println!("starting snowflake-id-worker with WORKER_ID: {worker_id}, DATA_CENTER_ID: {}, and EPOCH: {epoch:?}", args.data_center_id);
+        }))
+}
 
-    SnowflakeIdGenerator::with_epoch(args.data_center_id as i32, worker_id as i32, epoch)
+#[cfg(test)]
+fn snowflake_id_generator_from_env() -> generator::SnowflakeIdGenerator {
+    generator::SnowflakeIdGenerator::from_config(&WorkerConfig::try_from_env().unwrap())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use generator::{IdGenerator, SnowflakeIdGenerator, DEFAULT_LAYOUT};
     use serde_json::json;
     use std::collections::HashSet;
     use std::env;
+    use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
     use warp::test::request;
 
+    /// Checks that `resp` is an `application/problem+json` response, and parses its body.
+    fn problem_body(resp: &warp::http::Response<warp::hyper::body::Bytes>) -> serde_json::Value {
+        assert_eq!(resp.headers()["content-type"], problem::CONTENT_TYPE);
+        let problem: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(problem["status"], resp.status().as_u16());
+        problem
+    }
+
     #[test]
     fn test_env_parsing_default_values() {
         env::remove_var("WORKER_ID");
@@ -210,7 +899,7 @@ mod tests {
         env::remove_var("EPOCH");
 
         let mut generator = snowflake_id_generator_from_env();
-        let id = generator.real_time_generate();
+        let id = generator.generate().unwrap();
         assert!(id > 0);
     }
 
@@ -221,7 +910,7 @@ mod tests {
         env::remove_var("EPOCH");
 
         let mut generator = snowflake_id_generator_from_env();
-        let id = generator.real_time_generate();
+        let id = generator.generate().unwrap();
         assert!(id > 0);
 
         env::remove_var("WORKER_ID");
@@ -235,7 +924,7 @@ mod tests {
         env::remove_var("EPOCH");
 
         let mut generator = snowflake_id_generator_from_env();
-        let id = generator.real_time_generate();
+        let id = generator.generate().unwrap();
         assert!(id > 0);
 
         env::remove_var("WORKER_ID");
@@ -249,7 +938,7 @@ mod tests {
         env::set_var("EPOCH", "1420070400000"); // Discord's Epoch (2015-01-01 00:00:00 UTC)
 
         let mut generator = snowflake_id_generator_from_env();
-        let id = generator.real_time_generate();
+        let id = generator.generate().unwrap();
         assert!(id > 0);
 
         env::remove_var("WORKER_ID");
@@ -259,16 +948,103 @@ mod tests {
 
     #[test]
     fn test_env_parsing_max_values() {
-        env::set_var("WORKER_ID", MAX_WORKER_ID.to_string());
-        env::set_var("DATA_CENTER_ID", MAX_DATA_CENTER_ID.to_string());
+        env::set_var("WORKER_ID", DEFAULT_LAYOUT.max_worker_id().to_string());
+        env::set_var(
+            "DATA_CENTER_ID",
+            DEFAULT_LAYOUT.max_data_center_id().to_string(),
+        );
         env::remove_var("EPOCH");
 
         let mut generator = snowflake_id_generator_from_env();
-        let id = generator.real_time_generate();
+        let id = generator.generate().unwrap();
+        assert!(id > 0);
+
+        env::remove_var("WORKER_ID");
+        env::remove_var("DATA_CENTER_ID");
+    }
+
+    #[test]
+    fn test_env_parsing_custom_layout() {
+        env::set_var("WORKER_ID", "1000");
+        env::set_var("DATA_CENTER_ID", "0");
+        env::set_var("DATA_CENTER_ID_BITS", "0");
+        env::set_var("WORKER_ID_BITS", "10");
+        env::remove_var("EPOCH");
+
+        let config = WorkerConfig::try_from_env().unwrap();
+        let mut generator = SnowflakeIdGenerator::from_config(&config);
+        let id = generator.generate().unwrap();
         assert!(id > 0);
 
+        let parts = config.layout.decompose(id);
+        assert_eq!(parts.worker_id, 1000);
+        assert_eq!(parts.data_center_id, 0);
+
+        env::remove_var("WORKER_ID");
+        env::remove_var("DATA_CENTER_ID");
+        env::remove_var("DATA_CENTER_ID_BITS");
+        env::remove_var("WORKER_ID_BITS");
+    }
+
+    #[test]
+    fn test_env_parsing_invalid_layouts() {
+        let invalid_layouts = vec![
+            // doesn't add up to 63 bits
+            ("41", "5", "5", "13"),
+            ("41", "0", "10", "10"),
+            // no room for a timestamp
+            ("0", "20", "21", "22"),
+        ];
+
+        env::set_var("WORKER_ID", "0");
+        env::set_var("DATA_CENTER_ID", "0");
+        env::remove_var("EPOCH");
+        for (timestamp_bits, data_center_id_bits, worker_id_bits, sequence_bits) in invalid_layouts
+        {
+            env::set_var("TIMESTAMP_BITS", timestamp_bits);
+            env::set_var("DATA_CENTER_ID_BITS", data_center_id_bits);
+            env::set_var("WORKER_ID_BITS", worker_id_bits);
+            env::set_var("SEQUENCE_BITS", sequence_bits);
+
+            assert!(
+                matches!(
+                    WorkerConfig::try_from_env(),
+                    Err(ConfigError::InvalidLayout(_))
+                ),
+                "Layout {timestamp_bits}/{data_center_id_bits}/{worker_id_bits}/{sequence_bits} should be rejected"
+            );
+        }
+
+        // too small to represent the current time
+        env::set_var("TIMESTAMP_BITS", "20");
+        env::set_var("DATA_CENTER_ID_BITS", "11");
+        env::set_var("WORKER_ID_BITS", "10");
+        env::set_var("SEQUENCE_BITS", "22");
+        assert!(matches!(
+            WorkerConfig::try_from_env(),
+            Err(ConfigError::TimestampOverflow { timestamp_bits: 20 })
+        ));
+
+        // worker IDs must fit into the configured worker ID bits
+        env::set_var("TIMESTAMP_BITS", "41");
+        env::set_var("DATA_CENTER_ID_BITS", "7");
+        env::set_var("WORKER_ID_BITS", "3");
+        env::set_var("SEQUENCE_BITS", "12");
+        env::set_var("WORKER_ID", "8");
+        assert!(matches!(
+            WorkerConfig::try_from_env(),
+            Err(ConfigError::WorkerIdOutOfRange {
+                worker_id: 8,
+                max: 7
+            })
+        ));
+
         env::remove_var("WORKER_ID");
         env::remove_var("DATA_CENTER_ID");
+        env::remove_var("TIMESTAMP_BITS");
+        env::remove_var("DATA_CENTER_ID_BITS");
+        env::remove_var("WORKER_ID_BITS");
+        env::remove_var("SEQUENCE_BITS");
     }
 
     #[test]
@@ -288,7 +1064,7 @@ mod tests {
             env::remove_var("EPOCH");
 
             let mut generator = snowflake_id_generator_from_env();
-            let id = generator.real_time_generate();
+            let id = generator.generate().unwrap();
             assert!(id > 0, "Failed for hostname: {hostname}");
 
             env::remove_var("WORKER_ID");
@@ -298,102 +1074,563 @@ mod tests {
     }
 
     #[test]
-    #[should_panic(expected = "cannot split WORKER_ID from hostname")]
     fn test_env_parsing_hostname_no_dash() {
         env::set_var("WORKER_ID", "FROM_HOSTNAME");
         env::set_var("DATA_CENTER_ID", "0");
         env::set_var("HOSTNAME_FOR_TESTING", "nodasheshere");
         env::remove_var("EPOCH");
 
-        snowflake_id_generator_from_env();
+        let result = WorkerConfig::try_from_env();
+        assert!(
+            matches!(result, Err(ConfigError::HostnameWithoutWorkerId { .. })),
+            "unexpected result: {result:?}"
+        );
     }
 
     #[test]
-    #[should_panic(expected = "cannot parse WORKER_ID from hostname")]
     fn test_env_parsing_hostname_invalid_suffix() {
         env::set_var("WORKER_ID", "FROM_HOSTNAME");
         env::set_var("DATA_CENTER_ID", "0");
         env::set_var("HOSTNAME_FOR_TESTING", "hostname-invalid");
         env::remove_var("EPOCH");
 
-        snowflake_id_generator_from_env();
+        let result = WorkerConfig::try_from_env();
+        assert!(
+            matches!(result, Err(ConfigError::InvalidHostnameWorkerId { .. })),
+            "unexpected result: {result:?}"
+        );
     }
 
     #[test]
-    #[should_panic(expected = "cannot parse WORKER_ID from hostname")]
     fn test_env_parsing_hostname_empty_suffix() {
         env::set_var("WORKER_ID", "FROM_HOSTNAME");
         env::set_var("DATA_CENTER_ID", "0");
         env::set_var("HOSTNAME_FOR_TESTING", "hostname-");
         env::remove_var("EPOCH");
 
-        snowflake_id_generator_from_env();
+        let result = WorkerConfig::try_from_env();
+        assert!(
+            matches!(result, Err(ConfigError::InvalidHostnameWorkerId { .. })),
+            "unexpected result: {result:?}"
+        );
     }
 
     #[test]
-    #[should_panic(expected = "cannot parse WORKER_ID as a valid u8")]
-    fn test_env_parsing_invalid_worker_id() {
-        env::set_var("WORKER_ID", "invalid");
+    fn test_env_parsing_hostname_domain() {
+        env::set_var("WORKER_ID", "FROM_HOSTNAME");
         env::set_var("DATA_CENTER_ID", "0");
+        env::set_var("HOSTNAME_FOR_TESTING", "worker-3.svc.cluster.local");
         env::remove_var("EPOCH");
 
-        snowflake_id_generator_from_env();
+        let config = WorkerConfig::try_from_env().unwrap();
+        assert_eq!(config.worker_id, 3);
+
+        env::remove_var("WORKER_ID");
+        env::remove_var("DATA_CENTER_ID");
+        env::remove_var("HOSTNAME_FOR_TESTING");
     }
 
     #[test]
-    #[should_panic(expected = "DATA_CENTER_ID must be less than")]
-    fn test_env_parsing_data_center_id_too_large() {
-        env::set_var("WORKER_ID", "0");
-        env::set_var("DATA_CENTER_ID", "32");
+    fn test_env_parsing_hostname_patterns() {
+        env::set_var("WORKER_ID", "FROM_HOSTNAME");
+        env::set_var("DATA_CENTER_ID", "FROM_HOSTNAME");
+        env::set_var("HOSTNAME_FOR_TESTING", "dc2-worker-3.svc.cluster.local");
+        env::set_var("WORKER_ID_HOSTNAME_PATTERN", r"-worker-(?<id>\d+)\.");
+        env::set_var("DATA_CENTER_ID_HOSTNAME_PATTERN", r"^dc(\d+)-");
+        env::set_var("WORKER_ID_OFFSET", "16");
         env::remove_var("EPOCH");
 
-        snowflake_id_generator_from_env();
-    }
+        let config = WorkerConfig::try_from_env().unwrap();
+        assert_eq!(config.worker_id, 19);
+        assert_eq!(config.data_center_id, 2);
 
-    #[test]
-    #[should_panic(expected = "WORKER_ID must be less than")]
-    fn test_env_parsing_worker_id_too_large() {
-        env::set_var("WORKER_ID", "32");
-        env::set_var("DATA_CENTER_ID", "0");
-        env::remove_var("EPOCH");
+        // NOTE(ayubun): a negative offset is fine, as long as the worker ID doesn't go below 0
+        env::set_var("WORKER_ID_OFFSET", "-3");
+        let config = WorkerConfig::try_from_env().unwrap();
+        assert_eq!(config.worker_id, 0);
+
+        env::set_var("WORKER_ID_OFFSET", "-4");
+        let result = WorkerConfig::try_from_env();
+        assert!(
+            matches!(
+                result,
+                Err(ConfigError::WorkerIdOffsetOutOfRange {
+                    ordinal: 3,
+                    offset: -4
+                })
+            ),
+            "unexpected result: {result:?}"
+        );
+
+        env::set_var("WORKER_ID_OFFSET", "29");
+        let result = WorkerConfig::try_from_env();
+        assert!(
+            matches!(
+                result,
+                Err(ConfigError::WorkerIdOutOfRange {
+                    worker_id: 32,
+                    max: 31
+                })
+            ),
+            "unexpected result: {result:?}"
+        );
+        env::remove_var("WORKER_ID_OFFSET");
+
+        env::set_var("HOSTNAME_FOR_TESTING", "worker-3.svc.cluster.local");
+        let result = WorkerConfig::try_from_env();
+        assert!(
+            matches!(result, Err(ConfigError::HostnameWithoutWorkerId { .. })),
+            "unexpected result: {result:?}"
+        );
+
+        env::set_var("HOSTNAME_FOR_TESTING", "dc2-worker-x.svc.cluster.local");
+        env::set_var("WORKER_ID_HOSTNAME_PATTERN", r"-worker-(\w+)\.");
+        let result = WorkerConfig::try_from_env();
+        assert!(
+            matches!(result, Err(ConfigError::InvalidHostnameWorkerId { .. })),
+            "unexpected result: {result:?}"
+        );
+
+        env::set_var("HOSTNAME_FOR_TESTING", "west-worker-3.svc.cluster.local");
+        env::set_var("WORKER_ID_HOSTNAME_PATTERN", r"-worker-(\d+)\.");
+        let result = WorkerConfig::try_from_env();
+        assert!(
+            matches!(result, Err(ConfigError::InvalidHostnameDataCenterId { .. })),
+            "unexpected result: {result:?}"
+        );
+
+        env::remove_var("DATA_CENTER_ID_HOSTNAME_PATTERN");
+        let result = WorkerConfig::try_from_env();
+        assert!(
+            matches!(result, Err(ConfigError::MissingDataCenterIdHostnamePattern)),
+            "unexpected result: {result:?}"
+        );
 
-        snowflake_id_generator_from_env();
+        env::remove_var("WORKER_ID");
+        env::remove_var("DATA_CENTER_ID");
+        env::remove_var("HOSTNAME_FOR_TESTING");
+        env::remove_var("WORKER_ID_HOSTNAME_PATTERN");
     }
 
     #[test]
-    #[should_panic(expected = "WORKER_ID must be less than")]
-    fn test_env_parsing_hostname_worker_id_too_large() {
+    fn test_env_parsing_invalid_hostname_patterns() {
         env::set_var("WORKER_ID", "FROM_HOSTNAME");
         env::set_var("DATA_CENTER_ID", "0");
-        env::set_var("HOSTNAME_FOR_TESTING", "hostname-32");
+        env::set_var("HOSTNAME_FOR_TESTING", "worker-3");
         env::remove_var("EPOCH");
 
-        snowflake_id_generator_from_env();
-    }
+        for pattern in [r"worker-(\d+", r"worker-\d+"] {
+            env::set_var("WORKER_ID_HOSTNAME_PATTERN", pattern);
+            let result = WorkerConfig::try_from_env();
+            assert!(
+                matches!(
+                    result,
+                    Err(ConfigError::InvalidHostnamePattern {
+                        variable: "WORKER_ID_HOSTNAME_PATTERN",
+                        ..
+                    })
+                ),
+                "unexpected result for {pattern}: {result:?}"
+            );
+        }
 
-    #[tokio::test]
-    async fn test_health_endpoint() {
         env::remove_var("WORKER_ID");
         env::remove_var("DATA_CENTER_ID");
-        env::remove_var("EPOCH");
         env::remove_var("HOSTNAME_FOR_TESTING");
+        env::remove_var("WORKER_ID_HOSTNAME_PATTERN");
+    }
 
-        let routes = create_routes();
+    #[test]
+    fn test_env_parsing_ip() {
+        env::set_var("WORKER_ID", "FROM_IP");
+        env::set_var("DATA_CENTER_ID", "1");
+        env::set_var("IP_PREFIX_LEN", "27");
+        env::set_var(
+            "IP_FOR_TESTING",
+            "127.0.0.1, ::1, 8.8.8.8, 10.0.4.77, 192.168.0.2",
+        );
+        env::remove_var("POD_IP");
+        env::remove_var("EPOCH");
 
-        let resp = request().method("GET").path("/health").reply(&routes).await;
+        // NOTE(ayubun): loopback and public addresses are skipped, so this is 10.0.4.77/27
+        let config = WorkerConfig::try_from_env().unwrap();
+        assert_eq!(config.worker_id, 13);
+        assert_eq!(config.data_center_id, 1);
+
+        env::set_var("POD_IP", "10.0.7.201");
+        env::set_var("IP_PREFIX_LEN", "22");
+        env::set_var("DATA_CENTER_ID", "FROM_IP");
+        let config = WorkerConfig::try_from_env().unwrap();
+        assert_eq!(config.worker_id, 969 & 31);
+        assert_eq!(config.data_center_id, 969 >> 5);
+
+        // NOTE(ayubun): without DATA_CENTER_ID=FROM_IP, the 10 bits don't fit into a worker ID
+        env::set_var("DATA_CENTER_ID", "1");
+        let result = WorkerConfig::try_from_env();
+        assert!(
+            matches!(
+                result,
+                Err(ConfigError::IpOutOfRange {
+                    prefix_len: 22,
+                    host: 969,
+                    max: 31,
+                    ..
+                })
+            ),
+            "unexpected result: {result:?}"
+        );
 
-        assert_eq!(resp.status(), 200);
-        assert_eq!(resp.body(), "OK");
-    }
+        env::set_var("IP_PREFIX_LEN", "33");
+        let result = WorkerConfig::try_from_env();
+        assert!(
+            matches!(
+                result,
+                Err(ConfigError::InvalidIpPrefixLen { prefix_len: 33 })
+            ),
+            "unexpected result: {result:?}"
+        );
+        env::remove_var("IP_PREFIX_LEN");
+
+        env::remove_var("POD_IP");
+        env::set_var("IP_FOR_TESTING", "127.0.0.1,8.8.8.8");
+        let result = WorkerConfig::try_from_env();
+        assert!(
+            matches!(result, Err(ConfigError::NoPrivateIp)),
+            "unexpected result: {result:?}"
+        );
+
+        // NOTE(ayubun): DATA_CENTER_ID=FROM_IP only makes sense along with WORKER_ID=FROM_IP
+        env::set_var("WORKER_ID", "0");
+        env::set_var("DATA_CENTER_ID", "FROM_IP");
+        let result = WorkerConfig::try_from_env();
+        assert!(
+            matches!(result, Err(ConfigError::InvalidDataCenterId { .. })),
+            "unexpected result: {result:?}"
+        );
 
-    #[tokio::test]
-    async fn test_generate_endpoint_no_payload() {
         env::remove_var("WORKER_ID");
         env::remove_var("DATA_CENTER_ID");
+        env::remove_var("IP_FOR_TESTING");
+    }
+
+    #[test]
+    fn test_env_parsing_invalid_worker_id() {
+        env::set_var("WORKER_ID", "invalid");
+        env::set_var("DATA_CENTER_ID", "0");
         env::remove_var("EPOCH");
-        env::remove_var("HOSTNAME_FOR_TESTING");
 
-        let routes = create_routes();
+        let result = WorkerConfig::try_from_env();
+        assert!(
+            matches!(result, Err(ConfigError::InvalidWorkerId { .. })),
+            "unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn test_env_parsing_lease() {
+        env::set_var("WORKER_ID", "FROM_LEASE");
+        env::set_var("DATA_CENTER_ID", "3");
+        env::remove_var("EPOCH");
+        env::remove_var("LEASE_STORE_URL");
+
+        let result = WorkerConfig::try_from_env();
+        assert!(
+            matches!(result, Err(ConfigError::MissingLeaseStore)),
+            "unexpected result: {result:?}"
+        );
+
+        env::set_var("LEASE_STORE_URL", "redis://redis:6379");
+        let config = WorkerConfig::try_from_env().unwrap();
+        assert_eq!(config.lease_store.as_deref(), Some("redis://redis:6379"));
+        assert_eq!(config.data_center_id, 3);
+        assert!(!config.lease_data_center_id);
+
+        env::set_var("DATA_CENTER_ID", "from_lease");
+        let config = WorkerConfig::try_from_env().unwrap();
+        assert!(config.lease_data_center_id);
+
+        // NOTE(ayubun): the data center ID can only be leased along with the worker ID
+        env::set_var("WORKER_ID", "0");
+        let result = WorkerConfig::try_from_env();
+        assert!(
+            matches!(result, Err(ConfigError::InvalidDataCenterId { .. })),
+            "unexpected result: {result:?}"
+        );
+
+        env::remove_var("WORKER_ID");
+        env::remove_var("DATA_CENTER_ID");
+        env::remove_var("LEASE_STORE_URL");
+    }
+
+    #[test]
+    fn test_lease_must_be_claimed() {
+        env::set_var("WORKER_ID", "FROM_LEASE");
+        env::set_var("DATA_CENTER_ID", "3");
+        env::remove_var("EPOCH");
+        env::set_var("LEASE_STORE_URL", "redis://redis:6379");
+
+        // NOTE(ayubun): the worker ID is only a placeholder until a lease is claimed, so IDs
+        // generated with it could collide with whoever actually holds worker ID 0
+        let result = try_create_routes();
+        assert!(
+            matches!(result.err(), Some(ConfigError::LeaseNotClaimed)),
+            "routes were created without claiming a lease"
+        );
+
+        let config = WorkerConfig::builder()
+            .lease_store("redis://redis:6379")
+            .build()
+            .unwrap();
+        let result = Worker::try_new(config);
+        assert!(
+            matches!(result.err(), Some(ConfigError::LeaseNotClaimed)),
+            "worker was created without claiming a lease"
+        );
+
+        env::remove_var("WORKER_ID");
+        env::remove_var("DATA_CENTER_ID");
+        env::remove_var("LEASE_STORE_URL");
+    }
+
+    #[test]
+    fn test_env_parsing_lockfile() {
+        env::set_var("WORKER_ID", "FROM_LOCKFILE");
+        env::set_var("DATA_CENTER_ID", "0");
+        env::remove_var("EPOCH");
+        env::remove_var("LOCKFILE_DIR");
+
+        let result = WorkerConfig::try_from_env();
+        assert!(
+            matches!(result, Err(ConfigError::MissingLockfileDir)),
+            "unexpected result: {result:?}"
+        );
+
+        env::set_var("LOCKFILE_DIR", "/var/lock/snowflake-id-worker");
+        let config = WorkerConfig::try_from_env().unwrap();
+        assert_eq!(
+            config.lockfile_dir.as_deref(),
+            Some(std::path::Path::new("/var/lock/snowflake-id-worker"))
+        );
+
+        env::remove_var("WORKER_ID");
+        env::remove_var("DATA_CENTER_ID");
+        env::remove_var("LOCKFILE_DIR");
+    }
+
+    #[test]
+    fn test_env_parsing_gossip() {
+        env::remove_var("WORKER_ID");
+        env::remove_var("DATA_CENTER_ID");
+        env::remove_var("EPOCH");
+
+        let config = WorkerConfig::try_from_env().unwrap();
+        assert_eq!(config.gossip_group, None);
+
+        env::set_var("GOSSIP_GROUP", "239.255.42.99:7474");
+        env::set_var("GOSSIP_INTERFACE", "10.0.0.5");
+        env::set_var("GOSSIP_INTERVAL_MS", "250");
+        let config = WorkerConfig::try_from_env().unwrap();
+        assert_eq!(
+            config.gossip_group,
+            Some("239.255.42.99:7474".parse().unwrap())
+        );
+        assert_eq!(
+            config.gossip_interface,
+            std::net::Ipv4Addr::new(10, 0, 0, 5)
+        );
+        assert_eq!(config.gossip_interval, Duration::from_millis(250));
+
+        env::set_var("GOSSIP_INTERVAL_MS", "0");
+        let result = WorkerConfig::try_from_env();
+        assert!(
+            matches!(result, Err(ConfigError::InvalidGossipInterval)),
+            "unexpected result: {result:?}"
+        );
+        env::remove_var("GOSSIP_INTERVAL_MS");
+
+        env::set_var("GOSSIP_GROUP", "10.0.0.1:7474");
+        let result = WorkerConfig::try_from_env();
+        env::remove_var("GOSSIP_GROUP");
+        env::remove_var("GOSSIP_INTERFACE");
+        assert!(
+            matches!(result, Err(ConfigError::InvalidGossipGroup { .. })),
+            "unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn test_env_parsing_logging() {
+        env::remove_var("WORKER_ID");
+        env::remove_var("DATA_CENTER_ID");
+        env::remove_var("EPOCH");
+
+        let config = WorkerConfig::try_from_env().unwrap();
+        assert_eq!(config.log_format, LogFormat::Pretty);
+        assert_eq!(config.log_level, "info");
+
+        env::set_var("LOG_FORMAT", "json");
+        env::set_var("LOG_LEVEL", "warn,snowflake_id_worker=debug");
+        let config = WorkerConfig::try_from_env().unwrap();
+        assert_eq!(config.log_format, LogFormat::Json);
+        assert_eq!(config.log_level, "warn,snowflake_id_worker=debug");
+
+        env::set_var("LOG_LEVEL", "snowflake_id_worker=loud");
+        let result = WorkerConfig::try_from_env();
+        env::remove_var("LOG_FORMAT");
+        env::remove_var("LOG_LEVEL");
+        assert!(
+            matches!(result, Err(ConfigError::InvalidLogLevel { .. })),
+            "unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn test_env_parsing_data_center_id_too_large() {
+        env::set_var("WORKER_ID", "0");
+        env::set_var("DATA_CENTER_ID", "32");
+        env::remove_var("EPOCH");
+
+        let result = WorkerConfig::try_from_env();
+        assert!(
+            matches!(
+                result,
+                Err(ConfigError::DataCenterIdOutOfRange {
+                    data_center_id: 32,
+                    max: 31
+                })
+            ),
+            "unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn test_env_parsing_worker_id_too_large() {
+        env::set_var("WORKER_ID", "32");
+        env::set_var("DATA_CENTER_ID", "0");
+        env::remove_var("EPOCH");
+
+        let result = WorkerConfig::try_from_env();
+        assert!(
+            matches!(
+                result,
+                Err(ConfigError::WorkerIdOutOfRange {
+                    worker_id: 32,
+                    max: 31
+                })
+            ),
+            "unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn test_env_parsing_hostname_worker_id_too_large() {
+        env::set_var("WORKER_ID", "FROM_HOSTNAME");
+        env::set_var("DATA_CENTER_ID", "0");
+        env::set_var("HOSTNAME_FOR_TESTING", "hostname-32");
+        env::remove_var("EPOCH");
+
+        let result = WorkerConfig::try_from_env();
+        assert!(
+            matches!(
+                result,
+                Err(ConfigError::WorkerIdOutOfRange {
+                    worker_id: 32,
+                    max: 31
+                })
+            ),
+            "unexpected result: {result:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_health_endpoint() {
+        env::remove_var("WORKER_ID");
+        env::remove_var("DATA_CENTER_ID");
+        env::remove_var("EPOCH");
+        env::remove_var("HOSTNAME_FOR_TESTING");
+
+        let routes = create_routes();
+
+        let resp = request().method("GET").path("/health").reply(&routes).await;
+
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.body(), "OK");
+    }
+
+    #[tokio::test]
+    async fn test_probe_endpoints() {
+        let config = WorkerConfig::builder().build().unwrap();
+        let worker = Worker::new(config);
+        let routes = routes(worker.clone());
+
+        for path in ["/livez", "/readyz", "/startupz"] {
+            let resp = request().method("GET").path(path).reply(&routes).await;
+            assert_eq!(resp.status(), 200, "{path} should pass");
+            assert_eq!(resp.body(), "OK");
+        }
+
+        let resp = request()
+            .method("GET")
+            .path("/readyz?verbose")
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 200);
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body["status"], "ok");
+        for check in ["startup", "shutdown", "clock"] {
+            assert_eq!(body["checks"][check]["status"], "ok");
+        }
+
+        // NOTE(ayubun): while waiting out the high-water mark, the worker is alive but neither
+        // started nor ready, and shouldn't hand out any IDs
+        worker.set_starting(true);
+        let resp = request().method("GET").path("/livez").reply(&routes).await;
+        assert_eq!(resp.status(), 200);
+        for path in ["/readyz", "/startupz"] {
+            let resp = request().method("GET").path(path).reply(&routes).await;
+            let problem = problem_body(&resp);
+            assert_eq!(problem["type"], "urn:snowflake-id-worker:problem:not-ready");
+            assert!(problem["detail"].as_str().unwrap().starts_with("startup: "));
+        }
+        let resp = request().method("GET").path("/health").reply(&routes).await;
+        assert_eq!(problem_body(&resp)["detail"], "STARTING");
+        let resp = request()
+            .method("POST")
+            .path("/generate")
+            .reply(&routes)
+            .await;
+        assert_eq!(
+            problem_body(&resp)["type"],
+            "urn:snowflake-id-worker:problem:starting"
+        );
+        worker.set_starting(false);
+
+        worker.begin_shutdown();
+        let resp = request()
+            .method("GET")
+            .path("/startupz")
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 200);
+        let resp = request()
+            .method("GET")
+            .path("/readyz?verbose=true")
+            .reply(&routes)
+            .await;
+        let problem = problem_body(&resp);
+        assert_eq!(problem["checks"]["shutdown"]["status"], "failed");
+        assert_eq!(problem["checks"]["clock"]["status"], "ok");
+        assert!(problem["checks"]["clock"]["detail"].is_null());
+    }
+
+    #[tokio::test]
+    async fn test_generate_endpoint_no_payload() {
+        env::remove_var("WORKER_ID");
+        env::remove_var("DATA_CENTER_ID");
+        env::remove_var("EPOCH");
+        env::remove_var("HOSTNAME_FOR_TESTING");
+
+        let routes = create_routes();
 
         let resp = request()
             .method("POST")
@@ -430,6 +1667,7 @@ mod tests {
             .await;
 
         assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers()["content-type"], "application/json");
         let body = std::str::from_utf8(resp.body()).unwrap();
 
         let ids: Vec<i64> = serde_json::from_str(body).unwrap();
@@ -437,7 +1675,410 @@ mod tests {
 
         let unique_ids: HashSet<i64> = ids.iter().cloned().collect();
         assert_eq!(unique_ids.len(), 10, "All IDs should be unique");
-        assert!(ids.iter().all(|&id| id > 0), "All IDs should be positive");
+        assert!(ids.iter().all(|&id| id > 0), "All IDs should be positive");
+    }
+
+    #[tokio::test]
+    async fn test_generate_endpoint_ndjson() {
+        env::remove_var("WORKER_ID");
+        env::remove_var("DATA_CENTER_ID");
+        env::remove_var("EPOCH");
+        env::remove_var("HOSTNAME_FOR_TESTING");
+
+        let routes = create_routes();
+
+        let resp = request()
+            .method("POST")
+            .path("/generate")
+            .header("accept", "application/x-ndjson")
+            .json(&json!({"count": 10}))
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers()["content-type"], "application/x-ndjson");
+        let body = std::str::from_utf8(resp.body()).unwrap();
+        assert!(body.ends_with('\n'));
+        let ids: Vec<i64> = body
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let unique_ids: HashSet<i64> = ids.iter().cloned().collect();
+        assert_eq!(unique_ids.len(), 10, "All IDs should be unique");
+
+        // NOTE(ayubun): each line is still a JSON value, so `id_format` applies
+        let resp = request()
+            .method("POST")
+            .path("/generate")
+            .json(&json!({"count": 2, "format": "ndjson", "id_format": "string"}))
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers()["content-type"], "application/x-ndjson");
+        let body = std::str::from_utf8(resp.body()).unwrap();
+        for line in body.lines() {
+            let id: String = serde_json::from_str(line).unwrap();
+            assert!(id.parse::<i64>().unwrap() > 0);
+        }
+
+        let resp = request()
+            .method("POST")
+            .path("/generate")
+            .header("accept", "application/x-ndjson")
+            .json(&json!({"count": 2500, "stream": true}))
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 200);
+        let body = std::str::from_utf8(resp.body()).unwrap();
+        let unique_ids: HashSet<&str> = body.lines().collect();
+        assert_eq!(unique_ids.len(), 2500, "All IDs should be unique");
+    }
+
+    #[tokio::test]
+    async fn test_generate_endpoint_text() {
+        env::remove_var("WORKER_ID");
+        env::remove_var("DATA_CENTER_ID");
+        env::remove_var("EPOCH");
+        env::remove_var("HOSTNAME_FOR_TESTING");
+
+        let routes = create_routes();
+
+        let resp = request()
+            .method("POST")
+            .path("/generate")
+            .header("accept", "text/plain")
+            .json(&json!({"count": 10, "id_format": "string"}))
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers()["content-type"], "text/plain; charset=utf-8");
+        let body = std::str::from_utf8(resp.body()).unwrap();
+        let ids: Vec<i64> = body.lines().map(|line| line.parse().unwrap()).collect();
+        let unique_ids: HashSet<i64> = ids.iter().cloned().collect();
+        assert_eq!(unique_ids.len(), 10, "All IDs should be unique");
+
+        // NOTE(ayubun): `format` wins over the `Accept` header
+        let resp = request()
+            .method("POST")
+            .path("/generate")
+            .header("accept", "application/json")
+            .json(&json!({"count": 3, "format": "text"}))
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.headers()["content-type"], "text/plain; charset=utf-8");
+        assert_eq!(std::str::from_utf8(resp.body()).unwrap().lines().count(), 3);
+
+        let resp = request()
+            .method("POST")
+            .path("/generate")
+            .json(&json!({"format": "xml"}))
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_generate_endpoint_binary() {
+        env::remove_var("WORKER_ID");
+        env::remove_var("DATA_CENTER_ID");
+        env::remove_var("EPOCH");
+        env::remove_var("HOSTNAME_FOR_TESTING");
+
+        let routes = create_routes();
+
+        for (accept, payload) in [
+            ("application/octet-stream", json!({"count": 10})),
+            ("application/json", json!({"count": 10, "format": "binary"})),
+            (
+                "application/octet-stream",
+                json!({"count": 10, "stream": true}),
+            ),
+        ] {
+            let resp = request()
+                .method("POST")
+                .path("/generate")
+                .header("accept", accept)
+                .json(&payload)
+                .reply(&routes)
+                .await;
+            assert_eq!(resp.status(), 200);
+            assert_eq!(resp.headers()["content-type"], "application/octet-stream");
+            assert_eq!(resp.headers()[ID_COUNT_HEADER], "10");
+            assert_eq!(resp.body().len(), 80, "Each ID should be 8 bytes");
+
+            let ids: Vec<i64> = resp
+                .body()
+                .chunks_exact(8)
+                .map(|id| i64::from_be_bytes(id.try_into().unwrap()))
+                .collect();
+            let unique_ids: HashSet<i64> = ids.iter().cloned().collect();
+            assert_eq!(unique_ids.len(), 10, "All IDs should be unique");
+            assert!(
+                ids.windows(2).all(|w| w[0] < w[1]),
+                "IDs should keep increasing"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_generate_endpoint_accept_header() {
+        env::remove_var("WORKER_ID");
+        env::remove_var("DATA_CENTER_ID");
+        env::remove_var("EPOCH");
+        env::remove_var("HOSTNAME_FOR_TESTING");
+
+        let routes = create_routes();
+
+        for (accept, content_type) in [
+            ("*/*", "application/json"),
+            ("application/json", "application/json"),
+            ("text/html, text/plain;q=0.9", "text/plain; charset=utf-8"),
+            ("application/x-ndjson, */*", "application/x-ndjson"),
+            // NOTE(ayubun): the weights matter, not the order
+            (
+                "text/plain;q=0.5, application/x-ndjson",
+                "application/x-ndjson",
+            ),
+            (
+                "application/json;q=0.1, application/octet-stream;q=0.8",
+                "application/octet-stream",
+            ),
+            ("text/*;q=0.9, */*;q=0.2", "text/plain; charset=utf-8"),
+            // NOTE(ayubun): q=0 means "not this one", even when a wildcard would allow it
+            ("application/json;q=0, */*", "application/x-ndjson"),
+            ("text/plain;q=0, text/*", "application/json"),
+            (
+                "application/json; q=0, text/plain; Q=0.3",
+                "text/plain; charset=utf-8",
+            ),
+            // NOTE(ayubun): nothing we can write, so JSON it is
+            ("image/png", "application/json"),
+            ("text/plain;q=abc", "application/json"),
+        ] {
+            let resp = request()
+                .method("POST")
+                .path("/generate")
+                .header("accept", accept)
+                .reply(&routes)
+                .await;
+            assert_eq!(resp.status(), 200);
+            assert_eq!(resp.headers()["content-type"], content_type, "{accept}");
+        }
+
+        // NOTE(ayubun): an Accept header that isn't valid UTF-8 can't be read, so it's as good as
+        // not being there
+        let accept = warp::http::HeaderValue::from_bytes(b"text/plain, \xff").unwrap();
+        let resp = request()
+            .method("POST")
+            .path("/generate")
+            .header("accept", accept)
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers()["content-type"], "application/json");
+    }
+
+    #[tokio::test]
+    async fn test_generate_endpoint_max_batch_size() {
+        let config = WorkerConfig::builder().max_batch_size(100).build().unwrap();
+        let routes = create_routes_with(config);
+
+        let resp = request()
+            .method("POST")
+            .path("/generate")
+            .json(&json!({"count": 100}))
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 200);
+
+        let resp = request()
+            .method("POST")
+            .path("/generate")
+            .json(&json!({"count": 101}))
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 413);
+        let problem = problem_body(&resp);
+        assert_eq!(
+            problem["type"],
+            "urn:snowflake-id-worker:problem:batch-too-large"
+        );
+        assert_eq!(
+            problem["detail"],
+            "Invalid count: must be at most 100 (MAX_BATCH_SIZE)"
+        );
+        assert_eq!(problem["field"], "count");
+
+        // NOTE(ayubun): this used to go straight into `Vec::with_capacity`
+        let resp = request()
+            .method("POST")
+            .path("/generate")
+            .json(&json!({"count": 10_000_000_000i64}))
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 413);
+
+        let result = WorkerConfig::builder().max_batch_size(0).build();
+        assert!(matches!(result, Err(ConfigError::InvalidMaxBatchSize)));
+    }
+
+    #[tokio::test]
+    async fn test_generate_endpoint_streamed() {
+        let config = WorkerConfig::builder()
+            .max_batch_size(100)
+            .max_stream_size(3000)
+            .build()
+            .unwrap();
+        let routes = create_routes_with(config);
+
+        // NOTE(ayubun): streamed responses aren't held in memory, so they can go past
+        // MAX_BATCH_SIZE
+        let resp = request()
+            .method("POST")
+            .path("/generate")
+            .json(&json!({"count": 2500, "stream": true}))
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers()["content-type"], "application/json");
+        assert!(
+            !resp.headers().contains_key("content-length"),
+            "Streamed responses should be chunked"
+        );
+        let ids: Vec<i64> = serde_json::from_slice(resp.body()).unwrap();
+        let unique_ids: HashSet<i64> = ids.iter().cloned().collect();
+        assert_eq!(unique_ids.len(), 2500, "All IDs should be unique");
+
+        let resp = request()
+            .method("POST")
+            .path("/generate")
+            .json(&json!({"count": 1, "stream": true, "id_format": "string"}))
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 200);
+        let ids: Vec<String> = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(ids.len(), 1);
+
+        // NOTE(ayubun): ...but they're still capped by MAX_STREAM_SIZE
+        let resp = request()
+            .method("POST")
+            .path("/generate")
+            .json(&json!({"count": 3001, "stream": true}))
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 413);
+        let problem = problem_body(&resp);
+        assert_eq!(
+            problem["type"],
+            "urn:snowflake-id-worker:problem:batch-too-large"
+        );
+        assert_eq!(
+            problem["detail"],
+            "Invalid count: must be at most 3000 (MAX_STREAM_SIZE)"
+        );
+        assert_eq!(problem["field"], "count");
+        let result = WorkerConfig::builder().max_stream_size(0).build();
+        assert!(matches!(result, Err(ConfigError::InvalidMaxStreamSize)));
+
+        // NOTE(ayubun): each streamed request is observed once, not once per chunk
+        let resp = request()
+            .method("GET")
+            .path("/metrics")
+            .reply(&routes)
+            .await;
+        let body = std::str::from_utf8(resp.body()).unwrap();
+        for (name, value) in [
+            ("snowflake_ids_generated_total", 2501.0),
+            ("snowflake_batch_size_count", 2.0),
+            ("snowflake_batch_size_sum", 2501.0),
+            ("snowflake_generate_duration_seconds_count", 2.0),
+        ] {
+            assert_eq!(
+                metric_value(body, name, &[]),
+                Some(value),
+                "Unexpected value for {name} in:\n{body}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_info_endpoint() {
+        let config = WorkerConfig::builder()
+            .worker_id(7)
+            .data_center_id(3)
+            .epoch(UNIX_EPOCH + Duration::from_millis(1420070400000))
+            .max_batch_size(500)
+            .build()
+            .unwrap();
+        let routes = create_routes_with(config);
+
+        let resp = request().method("GET").path("/info").reply(&routes).await;
+        assert_eq!(resp.status(), 200);
+        let info: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(
+            info,
+            json!({
+                "worker_id": 7,
+                "data_center_id": 3,
+                "epoch_ms": 1420070400000u64,
+                "timestamp_bits": 41,
+                "data_center_id_bits": 5,
+                "worker_id_bits": 5,
+                "sequence_bits": 12,
+                "max_batch_size": 500,
+                "max_stream_size": 10_000_000,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_generate_endpoint_with_string_ids() {
+        env::remove_var("WORKER_ID");
+        env::remove_var("DATA_CENTER_ID");
+        env::remove_var("EPOCH");
+        env::remove_var("HOSTNAME_FOR_TESTING");
+
+        let routes = create_routes();
+
+        // NOTE(ayubun): "number" is the default, and should match leaving id_format out entirely
+        let resp = request()
+            .method("POST")
+            .path("/generate")
+            .json(&json!({"count": 10, "id_format": "number"}))
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 200);
+        let ids: Vec<i64> = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(ids.len(), 10);
+
+        let resp = request()
+            .method("POST")
+            .path("/generate")
+            .json(&json!({"count": 10, "id_format": "string"}))
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 200);
+        let ids: Vec<String> = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(ids.len(), 10);
+        let unique_ids: HashSet<i64> = ids.iter().map(|id| id.parse().unwrap()).collect();
+        assert_eq!(unique_ids.len(), 10, "All IDs should be unique");
+        assert!(
+            unique_ids.iter().all(|&id| id > 0),
+            "All IDs should be positive"
+        );
+
+        let resp = request()
+            .method("POST")
+            .path("/generate")
+            .json(&json!({"count": 10, "id_format": "hex"}))
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 400);
+        let problem = problem_body(&resp);
+        assert_eq!(
+            problem["detail"],
+            "Invalid id_format: must be \"number\" or \"string\""
+        );
+        assert_eq!(problem["field"], "id_format");
     }
 
     #[tokio::test]
@@ -544,6 +2185,56 @@ mod tests {
         assert_eq!(resp.status(), 405); // Method Not Allowed
     }
 
+    #[tokio::test]
+    async fn test_generate_get_endpoint() {
+        let config = WorkerConfig::builder()
+            .allow_get_generate(true)
+            .max_batch_size(100)
+            .max_stream_size(1000)
+            .build()
+            .unwrap();
+        let routes = create_routes_with(config);
+
+        let resp = request()
+            .method("GET")
+            .path("/generate")
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers()["cache-control"], "no-store");
+        let ids: Vec<i64> = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(ids.len(), 1);
+
+        let resp = request()
+            .method("GET")
+            .path("/generate?count=5&format=text")
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers()["cache-control"], "no-store");
+        let body = std::str::from_utf8(resp.body()).unwrap();
+        let unique_ids: HashSet<i64> = body.lines().map(|line| line.parse().unwrap()).collect();
+        assert_eq!(unique_ids.len(), 5, "All IDs should be unique");
+
+        // NOTE(ayubun): the same validation as `POST /generate` applies
+        for (query, status) in [
+            ("count=abc", 400),
+            ("count=0", 400),
+            ("count=5&id_format=hex", 400),
+            ("count=101", 413),
+            ("count=1000&stream=true", 200),
+            ("count=1001&stream=true", 413),
+        ] {
+            let resp = request()
+                .method("GET")
+                .path(&format!("/generate?{query}"))
+                .reply(&routes)
+                .await;
+            assert_eq!(resp.status(), status, "{query}");
+            assert_eq!(resp.headers()["cache-control"], "no-store");
+        }
+    }
+
     #[tokio::test]
     async fn test_non_existent_endpoints() {
         env::remove_var("WORKER_ID");
@@ -562,6 +2253,110 @@ mod tests {
         assert_eq!(resp.status(), 404); // i bet u know this one ( ˙꒳˙ )
     }
 
+    #[tokio::test]
+    async fn test_error_responses_are_problems() {
+        let config = WorkerConfig::builder().max_batch_size(100).build().unwrap();
+        let routes = create_routes_with(config);
+
+        for (method, path, body, problem_type, field) in [
+            ("POST", "/generate", "{not json", "invalid-json", None),
+            (
+                "POST",
+                "/generate",
+                r#"{"count": 0}"#,
+                "invalid-field",
+                Some("count"),
+            ),
+            (
+                "POST",
+                "/generate",
+                r#"{"count": 101}"#,
+                "batch-too-large",
+                Some("count"),
+            ),
+            (
+                "POST",
+                "/generate",
+                r#"{"format": "xml"}"#,
+                "invalid-field",
+                Some("format"),
+            ),
+            (
+                "POST",
+                "/decode",
+                r#"{"ids": []}"#,
+                "invalid-field",
+                Some("ids"),
+            ),
+            ("POST", "/decode", "[]", "invalid-json", None),
+            ("GET", "/generate", "", "method-not-allowed", None),
+            ("POST", "/health", "", "method-not-allowed", None),
+            ("GET", "/nonexistent", "", "not-found", None),
+        ] {
+            let resp = request()
+                .method(method)
+                .path(path)
+                .body(body)
+                .reply(&routes)
+                .await;
+            assert!(!resp.status().is_success(), "{method} {path} {body}");
+            let problem = problem_body(&resp);
+            assert_eq!(
+                problem["type"],
+                format!("urn:snowflake-id-worker:problem:{problem_type}"),
+                "{method} {path} {body}"
+            );
+            assert!(problem["title"].is_string());
+            assert!(problem["detail"].is_string());
+            assert_eq!(problem["field"].as_str(), field, "{method} {path} {body}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_bodies_are_capped() {
+        let routes = create_routes_with(WorkerConfig::builder().build().unwrap());
+
+        // NOTE(ayubun): padding with whitespace keeps the body valid JSON, so it can only be
+        // turned away for its size
+        let body = format!(r#"{{"ids": [1]}}{}"#, " ".repeat(MAX_BODY_SIZE as usize));
+        for path in ["/generate", "/decode"] {
+            let resp = request()
+                .method("POST")
+                .path(path)
+                .body(&body)
+                .reply(&routes)
+                .await;
+            assert_eq!(resp.status(), 413, "{path}");
+            let problem = problem_body(&resp);
+            assert_eq!(
+                problem["type"], "urn:snowflake-id-worker:problem:payload-too-large",
+                "{path}"
+            );
+
+            // NOTE(ayubun): a chunked body has no content-length to check up front
+            let resp = request()
+                .method("POST")
+                .path(path)
+                .header("transfer-encoding", "chunked")
+                .reply(&routes)
+                .await;
+            assert_eq!(resp.status(), 411, "{path}");
+            let problem = problem_body(&resp);
+            assert_eq!(
+                problem["type"], "urn:snowflake-id-worker:problem:length-required",
+                "{path}"
+            );
+        }
+
+        // NOTE(ayubun): an empty POST doesn't need a content-length
+        let resp = request()
+            .method("POST")
+            .path("/generate")
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 200);
+    }
+
     #[tokio::test]
     async fn test_payload_edge_cases() {
         env::remove_var("WORKER_ID");
@@ -612,4 +2407,520 @@ mod tests {
             "Should contain error message about invalid JSON"
         );
     }
+
+    #[test]
+    fn test_decode_snowflake_id() {
+        // Discord's Epoch (2015-01-01 00:00:00 UTC)
+        let epoch = UNIX_EPOCH + Duration::from_millis(1420070400000);
+        let id = (1000 << 22) | (3 << 17) | (7 << 12) | 42;
+
+        let worker = Worker::new(WorkerConfig::builder().epoch(epoch).build().unwrap());
+        let decoded = worker.decode(id).unwrap();
+        assert_eq!(
+            decoded,
+            DecodedId {
+                id,
+                timestamp_ms: 1000,
+                timestamp_utc: "2015-01-01T00:00:01.000Z".to_string(),
+                data_center_id: 3,
+                worker_id: 7,
+                sequence: 42,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_decode_endpoint_round_trip() {
+        env::set_var("WORKER_ID", "7");
+        env::set_var("DATA_CENTER_ID", "3");
+        env::set_var("EPOCH", "1420070400000");
+        env::remove_var("HOSTNAME_FOR_TESTING");
+
+        let routes = create_routes();
+
+        let resp = request()
+            .method("POST")
+            .path("/generate")
+            .json(&json!({"count": 5}))
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 200);
+        let ids: Vec<i64> = serde_json::from_slice(resp.body()).unwrap();
+
+        let resp = request()
+            .method("POST")
+            .path("/decode")
+            .json(&json!({ "ids": ids }))
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 200);
+
+        let decoded: Vec<serde_json::Value> = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(decoded.len(), 5);
+
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH + Duration::from_millis(1420070400000))
+            .unwrap()
+            .as_millis() as i64;
+        for (id, part) in ids.iter().zip(decoded.iter()) {
+            assert_eq!(part["id"], *id);
+            assert_eq!(part["worker_id"], 7);
+            assert_eq!(part["data_center_id"], 3);
+            let timestamp_ms = part["timestamp_ms"].as_i64().unwrap();
+            assert!(
+                (now_ms - timestamp_ms).abs() < 60_000,
+                "timestamp should be recent"
+            );
+            assert!(part["timestamp_utc"].as_str().unwrap().ends_with('Z'));
+        }
+
+        env::remove_var("WORKER_ID");
+        env::remove_var("DATA_CENTER_ID");
+        env::remove_var("EPOCH");
+    }
+
+    #[tokio::test]
+    async fn test_decode_endpoint_edge_cases() {
+        env::remove_var("WORKER_ID");
+        env::remove_var("DATA_CENTER_ID");
+        env::remove_var("EPOCH");
+        env::remove_var("HOSTNAME_FOR_TESTING");
+
+        let routes = create_routes();
+
+        let resp = request()
+            .method("POST")
+            .path("/decode")
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 400);
+
+        let resp = request()
+            .method("POST")
+            .path("/decode")
+            .json(&json!({"ids": []}))
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 400);
+        let body = std::str::from_utf8(resp.body()).unwrap();
+        assert!(body.contains("Invalid ids"));
+
+        let resp = request()
+            .method("POST")
+            .path("/decode")
+            .json(&json!({"ids": [-1]}))
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 400);
+        let body = std::str::from_utf8(resp.body()).unwrap();
+        assert!(body.contains("Invalid id"));
+    }
+
+    #[tokio::test]
+    async fn test_decode_endpoint_rejects_timestamps_past_9999() {
+        // NOTE(ayubun): with this many timestamp bits, the largest IDs are hundreds of thousands
+        // of years past EPOCH, which RFC 3339 can't write down
+        let config = WorkerConfig::builder()
+            .layout(BitLayout {
+                timestamp_bits: 53,
+                data_center_id_bits: 2,
+                worker_id_bits: 2,
+                sequence_bits: 6,
+            })
+            .build()
+            .unwrap();
+        let worker = Worker::new(config);
+        assert_eq!(
+            worker.decode(i64::MAX),
+            Err(DecodeError::TimestampOutOfRange { id: i64::MAX })
+        );
+        let routes = routes(worker.clone());
+
+        let resp = request()
+            .method("POST")
+            .path("/decode")
+            .json(&json!({"ids": [worker.generate().unwrap(), i64::MAX]}))
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 400);
+        let body = problem_body(&resp);
+        assert_eq!(
+            body["type"],
+            "urn:snowflake-id-worker:problem:invalid-field"
+        );
+        assert_eq!(body["field"], "ids");
+        assert!(body["detail"].as_str().unwrap().contains("Invalid id"));
+    }
+
+    #[tokio::test]
+    async fn test_clock_regression_surfaces_in_endpoints() {
+        env::remove_var("WORKER_ID");
+        env::remove_var("DATA_CENTER_ID");
+        env::remove_var("EPOCH");
+        env::remove_var("HOSTNAME_FOR_TESTING");
+        env::set_var("CLOCK_REGRESSION_POLICY", "fail");
+
+        let config = WorkerConfig::try_from_env().unwrap();
+        env::remove_var("CLOCK_REGRESSION_POLICY");
+        let mut generator = SnowflakeIdGenerator::from_config(&config);
+        let now_ms = generator::millis_since(config.epoch);
+        generator.pretend_last_issued_at(now_ms + 60_000);
+        let routes = routes(Worker::with_generator(config, generator));
+
+        let resp = request()
+            .method("POST")
+            .path("/generate")
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 503);
+        let problem = problem_body(&resp);
+        assert_eq!(
+            problem["type"],
+            "urn:snowflake-id-worker:problem:clock-regression"
+        );
+        assert!(problem["detail"]
+            .as_str()
+            .unwrap()
+            .contains("Clock moved backwards"));
+
+        let resp = request().method("GET").path("/health").reply(&routes).await;
+        assert_eq!(resp.status(), 503);
+        assert_eq!(resp.headers()["x-clock-regression-events"], "1");
+        let problem = problem_body(&resp);
+        assert!(problem["detail"]
+            .as_str()
+            .unwrap()
+            .starts_with("CLOCK_REGRESSION"));
+
+        // NOTE(ayubun): the process is still fine, it just shouldn't get any traffic
+        let resp = request().method("GET").path("/livez").reply(&routes).await;
+        assert_eq!(resp.status(), 200);
+        let resp = request().method("GET").path("/readyz").reply(&routes).await;
+        assert_eq!(resp.status(), 503);
+        assert!(problem_body(&resp)["detail"]
+            .as_str()
+            .unwrap()
+            .starts_with("clock: "));
+
+        let resp = request()
+            .method("GET")
+            .path("/metrics")
+            .reply(&routes)
+            .await;
+        let body = std::str::from_utf8(resp.body()).unwrap();
+        assert_eq!(
+            metric_value(body, "snowflake_clock_regressions_total", &[]),
+            Some(1.0)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_clock_regression_wait_does_not_block_the_runtime() {
+        env::remove_var("WORKER_ID");
+        env::remove_var("DATA_CENTER_ID");
+        env::remove_var("EPOCH");
+        env::remove_var("HOSTNAME_FOR_TESTING");
+        env::remove_var("CLOCK_REGRESSION_POLICY");
+
+        let config = WorkerConfig::try_from_env().unwrap();
+        let mut generator = SnowflakeIdGenerator::from_config(&config);
+        let now_ms = generator::millis_since(config.epoch);
+        generator.pretend_last_issued_at(now_ms + 300);
+        let worker = Worker::with_generator(config, generator);
+        let routes = routes(worker.clone());
+
+        let started_at = Instant::now();
+        let generate = tokio::spawn(async move {
+            request()
+                .method("POST")
+                .path("/generate")
+                .reply(&routes)
+                .await
+        });
+        // NOTE(ayubun): the test runtime only has one thread, so this sleep would only finish after
+        // the clock caught up if the wait had parked it
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(started_at.elapsed() < Duration::from_millis(250));
+        assert!(!generate.is_finished());
+
+        let resp = generate.await.unwrap();
+        assert_eq!(resp.status(), 200);
+        let ids: Vec<i64> = serde_json::from_slice(resp.body()).unwrap();
+        assert!(worker.decode(ids[0]).unwrap().timestamp_ms >= now_ms + 300);
+    }
+
+    #[tokio::test]
+    async fn test_state_file_high_water_mark() {
+        env::remove_var("WORKER_ID");
+        env::remove_var("DATA_CENTER_ID");
+        env::remove_var("EPOCH");
+        env::remove_var("HOSTNAME_FOR_TESTING");
+
+        let path = env::temp_dir().join(format!(
+            "snowflake-id-worker-{}-lib.state",
+            std::process::id()
+        ));
+        let config = WorkerConfig {
+            state_file: Some(path.clone()),
+            ..WorkerConfig::try_from_env().unwrap()
+        };
+
+        // NOTE(ayubun): pretend that a previous run issued IDs 200ms into the future
+        let mark = SystemTime::now() + Duration::from_millis(200);
+        state::write_high_water_mark(&path, mark).unwrap();
+
+        let generator = IdGenerator::from_config(&config);
+        let state_file = StateFile::open(&path, &config, &generator).await.unwrap();
+        assert!(
+            SystemTime::now() > mark,
+            "Should wait for the clock to pass the mark"
+        );
+
+        let id = generator.generate().unwrap();
+        let mark_ms = mark.duration_since(config.epoch).unwrap().as_millis() as i64;
+        let timestamp_ms = config.layout.decompose(id).timestamp_ms;
+        assert!(timestamp_ms > mark_ms);
+
+        drop(state_file);
+        let persisted = state::read_high_water_mark(&path).unwrap().unwrap();
+        assert_eq!(
+            persisted,
+            config.epoch + Duration::from_millis(timestamp_ms as u64)
+        );
+
+        // NOTE(ayubun): a mark far in the future should fail startup with the "fail" policy
+        let config = WorkerConfig {
+            high_water_mark_policy: HighWaterMarkPolicy::Fail,
+            ..config
+        };
+        state::write_high_water_mark(&path, SystemTime::now() + Duration::from_secs(60)).unwrap();
+        let generator = IdGenerator::from_config(&config);
+        let result = StateFile::open(&path, &config, &generator).await;
+        assert!(matches!(
+            result,
+            Err(ConfigError::BehindHighWaterMark { .. })
+        ));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_worker_config_builder() {
+        let config = WorkerConfig::builder()
+            .worker_id(1000)
+            .layout(BitLayout {
+                timestamp_bits: 41,
+                data_center_id_bits: 0,
+                worker_id_bits: 10,
+                sequence_bits: 12,
+            })
+            .build()
+            .unwrap();
+        assert_eq!(config.worker_id(), 1000);
+
+        let worker = Worker::new(config);
+        let ids = worker.generate_batch(10_000).unwrap();
+        let unique_ids: HashSet<i64> = ids.iter().cloned().collect();
+        assert_eq!(unique_ids.len(), ids.len(), "All IDs should be unique");
+        assert!(ids
+            .iter()
+            .all(|&id| worker.decode(id).unwrap().worker_id == 1000));
+
+        let result = WorkerConfig::builder().data_center_id(32).build();
+        assert!(matches!(
+            result,
+            Err(ConfigError::DataCenterIdOutOfRange { .. })
+        ));
+
+        let result = WorkerConfig::builder()
+            .epoch(SystemTime::now() + Duration::from_secs(60))
+            .build();
+        assert!(matches!(result, Err(ConfigError::EpochInFuture)));
+    }
+
+    #[tokio::test]
+    async fn test_metrics_endpoint() {
+        let config = WorkerConfig::builder()
+            .worker_id(7)
+            .data_center_id(3)
+            .build()
+            .unwrap();
+        let routes = create_routes_with(config);
+
+        let resp = request()
+            .method("POST")
+            .path("/generate")
+            .json(&json!({"count": 10}))
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 200);
+        let resp = request()
+            .method("POST")
+            .path("/generate")
+            .json(&json!({"count": 0}))
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 400);
+
+        let resp = request()
+            .method("GET")
+            .path("/metrics")
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers()["content-type"], metrics::CONTENT_TYPE);
+        let body = std::str::from_utf8(resp.body()).unwrap();
+
+        for (name, labels, value) in [
+            ("snowflake_ids_generated_total", vec![], 10.0),
+            (
+                "snowflake_http_requests_total",
+                vec![("status", "200")],
+                1.0,
+            ),
+            (
+                "snowflake_http_requests_total",
+                vec![("status", "400")],
+                1.0,
+            ),
+            ("snowflake_batch_size_bucket", vec![("le", "10")], 1.0),
+            ("snowflake_generate_duration_seconds_count", vec![], 1.0),
+            (
+                "snowflake_sequence_exhaustion_wait_seconds_count",
+                vec![],
+                0.0,
+            ),
+            ("snowflake_mutex_wait_seconds_count", vec![], 0.0),
+            ("snowflake_clock_regressions_total", vec![], 0.0),
+        ] {
+            let labels = [vec![("worker_id", "7"), ("data_center_id", "3")], labels].concat();
+            assert_eq!(
+                metric_value(body, name, &labels),
+                Some(value),
+                "Unexpected value for {name} {labels:?} in:\n{body}"
+            );
+        }
+
+        // NOTE(ayubun): the mutex wait is only recorded by the mutex generator
+        let config = WorkerConfig::builder()
+            .generator_kind(GeneratorKind::Mutex)
+            .build()
+            .unwrap();
+        let routes = create_routes_with(config);
+        request()
+            .method("POST")
+            .path("/generate")
+            .reply(&routes)
+            .await;
+        let resp = request()
+            .method("GET")
+            .path("/metrics")
+            .reply(&routes)
+            .await;
+        let body = std::str::from_utf8(resp.body()).unwrap();
+        assert_eq!(
+            metric_value(body, "snowflake_mutex_wait_seconds_count", &[]),
+            Some(1.0)
+        );
+    }
+
+    /// Finds the value of the series called `name` that has (at least) all of `labels`, in any
+    /// order, in a Prometheus text format body.
+    fn metric_value(body: &str, name: &str, labels: &[(&str, &str)]) -> Option<f64> {
+        body.lines().find_map(|line| {
+            let (series, value) = line.rsplit_once(' ')?;
+            let (series_name, series_labels) = series.split_once('{').unwrap_or((series, ""));
+            let has_labels = labels
+                .iter()
+                .all(|(key, val)| series_labels.contains(&format!("{key}=\"{val}\"")));
+            (series_name == name && has_labels).then(|| value.parse().unwrap())
+        })
+    }
+
+    #[tokio::test]
+    async fn test_create_routes_with_explicit_config() {
+        // NOTE(ayubun): the explicit config should win over anything in the environment
+        env::set_var("WORKER_ID", "invalid");
+        env::remove_var("DATA_CENTER_ID");
+        env::remove_var("EPOCH");
+        env::remove_var("HOSTNAME_FOR_TESTING");
+
+        let config = WorkerConfig::builder()
+            .worker_id(9)
+            .data_center_id(4)
+            .build()
+            .unwrap();
+        let routes = create_routes_with(config);
+        env::remove_var("WORKER_ID");
+
+        let resp = request()
+            .method("POST")
+            .path("/generate")
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 200);
+        let ids: Vec<i64> = serde_json::from_slice(resp.body()).unwrap();
+
+        let resp = request()
+            .method("POST")
+            .path("/decode")
+            .json(&json!({ "ids": ids }))
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), 200);
+        let decoded: Vec<serde_json::Value> = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(decoded[0]["worker_id"], 9);
+        assert_eq!(decoded[0]["data_center_id"], 4);
+    }
+
+    #[tokio::test]
+    async fn test_graceful_shutdown() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let config = WorkerConfig::builder()
+            .port(port)
+            .shutdown_drain(Duration::from_millis(500))
+            .build()
+            .unwrap();
+        let (signal_tx, signal_rx) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(serve(Worker::new(config), async move {
+            let _ = signal_rx.await;
+        }));
+
+        let client = reqwest::Client::new();
+        let url = |path: &str| format!("http://127.0.0.1:{port}{path}");
+        let mut resp = client.get(url("/health")).send().await;
+        while resp.is_err() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            resp = client.get(url("/health")).send().await;
+        }
+        assert_eq!(resp.unwrap().status(), 200);
+
+        signal_tx.send(()).unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let resp = client.get(url("/health")).send().await.unwrap();
+        assert_eq!(resp.status(), 503);
+        let problem: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(
+            problem["type"],
+            "urn:snowflake-id-worker:problem:shutting-down"
+        );
+        let resp = client.post(url("/generate")).send().await.unwrap();
+        assert_eq!(
+            resp.status(),
+            200,
+            "Requests should still be served while draining"
+        );
+        assert!(!server.is_finished(), "Should wait out the drain");
+
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("Should exit after the drain")
+            .unwrap()
+            .unwrap();
+    }
 }
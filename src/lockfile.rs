@@ -0,0 +1,152 @@
+//! Claims a worker ID by locking a slot file in a shared directory, for `WORKER_ID=FROM_LOCKFILE`.
+//!
+//! Every worker on a host points `LOCKFILE_DIR` at the same directory, and takes an exclusive
+//! `flock` on the first `worker-{id}.lock` that nobody else holds. The OS releases the lock when
+//! the process exits (even if it crashes), so the slot frees itself up for the next worker.
+
+use std::{
+    fs::{self, File, TryLockError},
+    io::{Seek, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{ConfigError, WorkerConfig};
+
+/// An exclusive lock on a worker ID slot, which is held until this is dropped.
+#[derive(Debug)]
+pub(crate) struct WorkerIdLock {
+    worker_id: u64,
+    path: PathBuf,
+    // NOTE(ayubun): never read, but closing the file is what releases the lock
+    _file: File,
+}
+
+impl WorkerIdLock {
+    /// Locks the first free slot in `dir` between `0` and the largest worker ID that fits into the
+    /// layout of `config`.
+    pub(crate) fn claim(dir: &Path, config: &WorkerConfig) -> Result<WorkerIdLock, ConfigError> {
+        let lockfile_error = |path: &Path| {
+            let path = path.to_path_buf();
+            move |source| ConfigError::Lockfile { path, source }
+        };
+        fs::create_dir_all(dir).map_err(lockfile_error(dir))?;
+
+        let max_worker_id = config.layout.max_worker_id();
+        for worker_id in 0..=max_worker_id {
+            let path = dir.join(format!("worker-{worker_id}.lock"));
+            // NOTE(ayubun): the file is never truncated before it's locked, since it might be
+            // someone else's
+            let mut file = File::options()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&path)
+                .map_err(lockfile_error(&path))?;
+            match file.try_lock() {
+                Ok(()) => {}
+                Err(TryLockError::WouldBlock) => continue,
+                Err(TryLockError::Error(source)) => return Err(lockfile_error(&path)(source)),
+            }
+
+            // NOTE(ayubun): the PID is only there to make it easier to tell who holds which slot
+            file.set_len(0)
+                .and_then(|()| file.rewind())
+                .and_then(|()| writeln!(file, "{}", std::process::id()))
+                .map_err(lockfile_error(&path))?;
+            tracing::info!(worker_id, lockfile = ?path, "locked worker ID slot");
+            return Ok(WorkerIdLock {
+                worker_id,
+                path,
+                _file: file,
+            });
+        }
+        Err(ConfigError::NoFreeWorkerId {
+            candidates: (max_worker_id + 1) as usize,
+        })
+    }
+
+    pub(crate) fn worker_id(&self) -> u64 {
+        self.worker_id
+    }
+}
+
+impl Drop for WorkerIdLock {
+    fn drop(&mut self) {
+        tracing::info!(worker_id = self.worker_id, lockfile = ?self.path, "releasing worker ID slot");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BitLayout, Worker};
+    use std::env;
+
+    #[test]
+    fn test_claim_worker_id_slots() {
+        let dir = env::temp_dir().join(format!(
+            "snowflake-id-worker-{}-lockfiles",
+            std::process::id()
+        ));
+        let config = WorkerConfig::builder()
+            .layout(BitLayout {
+                timestamp_bits: 41,
+                data_center_id_bits: 5,
+                worker_id_bits: 2,
+                sequence_bits: 15,
+            })
+            .build()
+            .unwrap();
+
+        // NOTE(ayubun): flock treats every open file separately, even within one process, so
+        // this behaves just like 4 workers starting up on the same host
+        let mut locks: Vec<WorkerIdLock> = (0..4)
+            .map(|_| WorkerIdLock::claim(&dir, &config).unwrap())
+            .collect();
+        let worker_ids: Vec<u64> = locks.iter().map(WorkerIdLock::worker_id).collect();
+        assert_eq!(worker_ids, vec![0, 1, 2, 3]);
+        let pid = fs::read_to_string(dir.join("worker-2.lock")).unwrap();
+        assert_eq!(pid.trim(), std::process::id().to_string());
+
+        let result = WorkerIdLock::claim(&dir, &config);
+        assert!(
+            matches!(result, Err(ConfigError::NoFreeWorkerId { candidates: 4 })),
+            "unexpected result: {result:?}"
+        );
+
+        // NOTE(ayubun): dropping a lock frees its slot up for the next worker
+        locks.remove(1);
+        let lock = WorkerIdLock::claim(&dir, &config).unwrap();
+        assert_eq!(lock.worker_id(), 1);
+
+        drop(locks);
+        drop(lock);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_worker_holds_its_slot() {
+        let dir = env::temp_dir().join(format!(
+            "snowflake-id-worker-{}-worker-lockfiles",
+            std::process::id()
+        ));
+        let config = || WorkerConfig::builder().lockfile_dir(&dir).build().unwrap();
+
+        // NOTE(ayubun): library users never go through run_worker_until, so the worker has to
+        // claim the slot itself rather than generating as worker 0
+        let first = Worker::try_new(config()).unwrap();
+        let second = Worker::try_new(config()).unwrap();
+        assert_eq!(first.config().worker_id, 0);
+        assert_eq!(second.config().worker_id, 1);
+
+        // NOTE(ayubun): the slot stays locked until every clone of the worker is gone
+        let clone = first.clone();
+        drop(first);
+        assert_eq!(Worker::try_new(config()).unwrap().config().worker_id, 2);
+        drop(clone);
+        assert_eq!(Worker::try_new(config()).unwrap().config().worker_id, 0);
+
+        drop(second);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,51 @@
+use std::io::IsTerminal;
+
+use tracing_subscriber::EnvFilter;
+
+use crate::WorkerConfig;
+
+pub(crate) const DEFAULT_LOG_LEVEL: &str = "info";
+
+/// How the worker writes its logs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable, one line per event
+    Pretty,
+    /// One JSON object per event, for log aggregators
+    Json,
+}
+
+/// Installs the global `tracing` subscriber for the worker binary, using the configured
+/// `LOG_FORMAT` and `LOG_LEVEL`.
+///
+/// This does nothing if a subscriber has already been installed (i.e. by an application that
+/// embeds the worker).
+pub(crate) fn init(config: &WorkerConfig) {
+    let mut filter =
+        EnvFilter::try_new(&config.log_level).expect("LOG_LEVEL is validated on build");
+    // NOTE(ayubun): warp logs its own "processing request" events inside of our request span,
+    // which doubles up on our "finished request" event. they're still available by asking for
+    // them explicitly (i.e. `LOG_LEVEL=info,warp=debug`)
+    if !config.log_level.contains("warp") {
+        filter = filter.add_directive("warp::filters::trace=warn".parse().unwrap());
+    }
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_ansi(std::io::stdout().is_terminal());
+    let _ = match config.log_format {
+        LogFormat::Pretty => subscriber.try_init(),
+        LogFormat::Json => subscriber
+            .json()
+            .with_current_span(true)
+            .with_span_list(false)
+            .try_init(),
+    };
+}
+
+/// Makes sure that `level` is a valid `LOG_LEVEL`, which can either be a plain level (i.e.
+/// `debug`) or a list of per-target directives (i.e. `warn,snowflake_id_worker=debug`).
+pub(crate) fn validate_level(level: &str) -> Result<(), String> {
+    EnvFilter::try_new(level)
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
@@ -1,9 +1,26 @@
-use snowflake_id_worker::{exit_signal, run_worker};
+use snowflake_id_worker::{run_worker, ConfigError, WorkerError};
+use std::process::ExitCode;
 
 #[tokio::main]
-async fn main() {
-    tokio::select!(
-        _ = exit_signal() => println!("Exiting from signal"),
-        _ = run_worker() => println!("Worker exited"),
-    )
+async fn main() -> ExitCode {
+    // NOTE(ayubun): the worker listens for SIGINT/SIGTERM itself, so that it can drain in-flight
+    // requests before exiting
+    match run_worker().await {
+        Ok(()) => {
+            tracing::info!("worker exited");
+            ExitCode::SUCCESS
+        }
+        // NOTE(ayubun): clap knows best how to render its own errors (and `--help`)
+        Err(WorkerError::Config(ConfigError::InvalidArgs(err))) => err.exit(),
+        Err(err) => {
+            // NOTE(ayubun): logging is configured from the worker config, so if that's what
+            // failed, there's nowhere to log to yet
+            if tracing::dispatcher::has_been_set() {
+                tracing::error!(error = %err, exit_code = err.exit_code(), "worker failed");
+            } else {
+                eprintln!("error: {err}");
+            }
+            ExitCode::from(err.exit_code())
+        }
+    }
 }
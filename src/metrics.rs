@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+
+use prometheus::{
+    core::Collector, exponential_buckets, Encoder, Histogram, HistogramOpts, IntCounter,
+    IntCounterVec, Opts, Registry, TextEncoder,
+};
+
+/// The content type of the Prometheus text exposition format.
+pub(crate) const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// The Prometheus metrics of a single worker, served by `GET /metrics`.
+///
+/// Every worker gets its own registry (rather than the global default one), so that every series
+/// can be tagged with `worker_id` and `data_center_id`, and so that several workers can live in
+/// the same process (i.e. in tests).
+pub(crate) struct Metrics {
+    registry: Registry,
+    pub(crate) ids_generated: IntCounter,
+    pub(crate) http_requests: IntCounterVec,
+    pub(crate) batch_size: Histogram,
+    pub(crate) generate_duration: Histogram,
+    pub(crate) sequence_exhaustion_wait: Histogram,
+    pub(crate) mutex_wait: Histogram,
+    pub(crate) clock_regressions: IntCounter,
+}
+
+impl Metrics {
+    pub(crate) fn new(worker_id: u64, data_center_id: u64) -> Metrics {
+        let labels = HashMap::from([
+            ("worker_id".to_string(), worker_id.to_string()),
+            ("data_center_id".to_string(), data_center_id.to_string()),
+        ]);
+        let registry = Registry::new_custom(Some("snowflake".to_string()), Some(labels))
+            .expect("metric labels are valid");
+
+        // NOTE(ayubun): most of the timings we care about are well under a millisecond, so these
+        // buckets go from 1µs up to ~0.26s
+        let duration_buckets = || exponential_buckets(0.000_001, 4.0, 10).unwrap();
+
+        let metrics = Metrics {
+            ids_generated: IntCounter::new("ids_generated_total", "Total number of IDs generated")
+                .unwrap(),
+            http_requests: IntCounterVec::new(
+                Opts::new("http_requests_total", "HTTP requests by status code"),
+                &["status"],
+            )
+            .unwrap(),
+            batch_size: Histogram::with_opts(
+                HistogramOpts::new("batch_size", "Number of IDs generated per request")
+                    .buckets(exponential_buckets(1.0, 10.0, 8).unwrap()),
+            )
+            .unwrap(),
+            generate_duration: Histogram::with_opts(
+                HistogramOpts::new(
+                    "generate_duration_seconds",
+                    "Time taken to generate each batch of IDs",
+                )
+                .buckets(duration_buckets()),
+            )
+            .unwrap(),
+            sequence_exhaustion_wait: Histogram::with_opts(
+                HistogramOpts::new(
+                    "sequence_exhaustion_wait_seconds",
+                    "Time spent waiting for the next millisecond after the sequence was used up",
+                )
+                .buckets(duration_buckets()),
+            )
+            .unwrap(),
+            mutex_wait: Histogram::with_opts(
+                HistogramOpts::new(
+                    "mutex_wait_seconds",
+                    "Time spent waiting for the generator lock (only with GENERATOR=mutex)",
+                )
+                .buckets(duration_buckets()),
+            )
+            .unwrap(),
+            clock_regressions: IntCounter::new(
+                "clock_regressions_total",
+                "Number of times the clock was seen moving backwards",
+            )
+            .unwrap(),
+            registry,
+        };
+
+        let collectors: [Box<dyn Collector>; 7] = [
+            Box::new(metrics.ids_generated.clone()),
+            Box::new(metrics.http_requests.clone()),
+            Box::new(metrics.batch_size.clone()),
+            Box::new(metrics.generate_duration.clone()),
+            Box::new(metrics.sequence_exhaustion_wait.clone()),
+            Box::new(metrics.mutex_wait.clone()),
+            Box::new(metrics.clock_regressions.clone()),
+        ];
+        for collector in collectors {
+            metrics
+                .registry
+                .register(collector)
+                .expect("metric names are unique");
+        }
+        metrics
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub(crate) fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("metrics can be encoded");
+        String::from_utf8(buffer).expect("metrics are valid UTF-8")
+    }
+}
@@ -0,0 +1,214 @@
+//! [RFC 7807](https://www.rfc-editor.org/rfc/rfc7807) `application/problem+json` error bodies,
+//! which every non-2xx HTTP response is written as.
+
+use warp::{
+    http::{header, StatusCode},
+    reject::{LengthRequired, MethodNotAllowed, PayloadTooLarge, UnsupportedMediaType},
+    Rejection, Reply,
+};
+
+pub(crate) const CONTENT_TYPE: &str = "application/problem+json";
+
+/// The stable `type` of a [`Problem`]. Clients should match on this instead of `detail`, which is
+/// only meant for humans and may change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ProblemType {
+    /// The request body isn't valid JSON (or doesn't have the expected shape)
+    InvalidJson,
+    /// The query string couldn't be parsed
+    InvalidQuery,
+    /// A field has a value that isn't allowed, i.e. `{"count": 0}`
+    InvalidField,
+    /// `count` is larger than `MAX_BATCH_SIZE`
+    BatchTooLarge,
+    /// The request body is larger than the server accepts
+    PayloadTooLarge,
+    /// The request body has no `content-length`, so its size can't be checked before it's read
+    LengthRequired,
+    /// The clock moved backwards, so the worker can't safely generate IDs
+    ClockRegression,
+    /// The worker is draining before it shuts down
+    ShuttingDown,
+    /// The worker is still starting up, i.e. waiting out the high-water mark in `STATE_FILE`
+    Starting,
+    /// The worker's lease on its worker ID was lost, or couldn't be renewed in time
+    LeaseLost,
+    /// The time since `EPOCH` no longer fits into `TIMESTAMP_BITS`
+    TimestampOverflow,
+    /// Another worker on `GOSSIP_GROUP` announced the same identity, or a different `EPOCH`
+    PeerConflict,
+    /// At least one of the checks behind a health probe failed
+    NotReady,
+    /// There's no endpoint at the requested path
+    NotFound,
+    /// The endpoint exists, but not for the requested method
+    MethodNotAllowed,
+    /// The request body has a content type that the endpoint doesn't accept
+    UnsupportedMediaType,
+    /// Anything that we didn't anticipate
+    Internal,
+}
+
+impl ProblemType {
+    fn slug(self) -> &'static str {
+        match self {
+            ProblemType::InvalidJson => "invalid-json",
+            ProblemType::InvalidQuery => "invalid-query",
+            ProblemType::InvalidField => "invalid-field",
+            ProblemType::BatchTooLarge => "batch-too-large",
+            ProblemType::PayloadTooLarge => "payload-too-large",
+            ProblemType::LengthRequired => "length-required",
+            ProblemType::ClockRegression => "clock-regression",
+            ProblemType::ShuttingDown => "shutting-down",
+            ProblemType::Starting => "starting",
+            ProblemType::LeaseLost => "lease-lost",
+            ProblemType::TimestampOverflow => "timestamp-overflow",
+            ProblemType::PeerConflict => "peer-conflict",
+            ProblemType::NotReady => "not-ready",
+            ProblemType::NotFound => "not-found",
+            ProblemType::MethodNotAllowed => "method-not-allowed",
+            ProblemType::UnsupportedMediaType => "unsupported-media-type",
+            ProblemType::Internal => "internal",
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            ProblemType::InvalidJson => "Invalid JSON",
+            ProblemType::InvalidQuery => "Invalid query string",
+            ProblemType::InvalidField => "Invalid field",
+            ProblemType::BatchTooLarge => "Batch too large",
+            ProblemType::PayloadTooLarge => "Payload too large",
+            ProblemType::LengthRequired => "Length required",
+            ProblemType::ClockRegression => "Clock moved backwards",
+            ProblemType::ShuttingDown => "Shutting down",
+            ProblemType::Starting => "Starting",
+            ProblemType::LeaseLost => "Worker ID lease lost",
+            ProblemType::TimestampOverflow => "Timestamp overflow",
+            ProblemType::PeerConflict => "Conflicting peer",
+            ProblemType::NotReady => "Not ready",
+            ProblemType::NotFound => "Not found",
+            ProblemType::MethodNotAllowed => "Method not allowed",
+            ProblemType::UnsupportedMediaType => "Unsupported media type",
+            ProblemType::Internal => "Internal server error",
+        }
+    }
+
+    fn status(self) -> StatusCode {
+        match self {
+            ProblemType::InvalidJson | ProblemType::InvalidQuery | ProblemType::InvalidField => {
+                StatusCode::BAD_REQUEST
+            }
+            ProblemType::BatchTooLarge | ProblemType::PayloadTooLarge => {
+                StatusCode::PAYLOAD_TOO_LARGE
+            }
+            ProblemType::LengthRequired => StatusCode::LENGTH_REQUIRED,
+            ProblemType::ClockRegression
+            | ProblemType::ShuttingDown
+            | ProblemType::Starting
+            | ProblemType::LeaseLost
+            | ProblemType::TimestampOverflow
+            | ProblemType::PeerConflict
+            | ProblemType::NotReady => StatusCode::SERVICE_UNAVAILABLE,
+            ProblemType::NotFound => StatusCode::NOT_FOUND,
+            ProblemType::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            ProblemType::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ProblemType::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// The URI that goes into the `type` member of the response.
+    pub(crate) fn uri(self) -> String {
+        format!("urn:snowflake-id-worker:problem:{}", self.slug())
+    }
+}
+
+/// A single error response, i.e.
+///
+/// ```json
+/// {
+///   "type": "urn:snowflake-id-worker:problem:invalid-field",
+///   "title": "Invalid field",
+///   "status": 400,
+///   "detail": "Invalid count: must be a positive integer",
+///   "field": "count"
+/// }
+/// ```
+#[derive(Debug)]
+pub(crate) struct Problem {
+    problem_type: ProblemType,
+    detail: String,
+    field: Option<&'static str>,
+    extensions: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Problem {
+    pub(crate) fn new(problem_type: ProblemType, detail: impl Into<String>) -> Problem {
+        Problem {
+            problem_type,
+            detail: detail.into(),
+            field: None,
+            extensions: serde_json::Map::new(),
+        }
+    }
+
+    /// Names the request field that caused the problem.
+    pub(crate) fn with_field(mut self, field: &'static str) -> Problem {
+        self.field = Some(field);
+        self
+    }
+
+    /// Adds an extra member to the body, on top of the standard ones.
+    pub(crate) fn with_extension(mut self, key: &str, value: serde_json::Value) -> Problem {
+        self.extensions.insert(key.to_string(), value);
+        self
+    }
+}
+
+impl Reply for Problem {
+    fn into_response(self) -> warp::reply::Response {
+        let status = self.problem_type.status();
+        let mut body = serde_json::json!({
+            "type": self.problem_type.uri(),
+            "title": self.problem_type.title(),
+            "status": status.as_u16(),
+            "detail": self.detail,
+            "field": self.field,
+        });
+        body.as_object_mut()
+            .expect("problem body is an object")
+            .extend(self.extensions);
+        let mut response = warp::reply::with_status(body.to_string(), status).into_response();
+        response.headers_mut().insert(
+            header::CONTENT_TYPE,
+            header::HeaderValue::from_static(CONTENT_TYPE),
+        );
+        response
+    }
+}
+
+/// Turns warp's rejections (i.e. for unknown routes and wrong methods) into [`Problem`]s, so that
+/// they get the same kind of body as every other error.
+///
+/// This never actually fails, but returning a [`Rejection`] keeps the error type of the routes the
+/// same as it was before they recovered.
+pub(crate) async fn recover(rejection: Rejection) -> Result<warp::reply::Response, Rejection> {
+    // NOTE(ayubun): the body size is checked before anything else on a matching route, so those
+    // rejections go first. otherwise the other method on the same path (i.e. `GET /generate`) wins
+    // with a 405
+    let problem = if rejection.is_not_found() {
+        Problem::new(ProblemType::NotFound, "There is no endpoint at this path")
+    } else if let Some(err) = rejection.find::<PayloadTooLarge>() {
+        Problem::new(ProblemType::PayloadTooLarge, err.to_string())
+    } else if let Some(err) = rejection.find::<LengthRequired>() {
+        Problem::new(ProblemType::LengthRequired, err.to_string())
+    } else if let Some(err) = rejection.find::<MethodNotAllowed>() {
+        Problem::new(ProblemType::MethodNotAllowed, err.to_string())
+    } else if let Some(err) = rejection.find::<UnsupportedMediaType>() {
+        Problem::new(ProblemType::UnsupportedMediaType, err.to_string())
+    } else {
+        tracing::error!(?rejection, "unhandled rejection");
+        Problem::new(ProblemType::Internal, "Something went wrong")
+    };
+    Ok(problem.into_response())
+}
@@ -0,0 +1,228 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    clock::ClockMonitor,
+    generator::{millis_since, IdGenerator},
+    ConfigError, WorkerConfig,
+};
+
+/// What the worker should do on startup when the clock is behind the high-water mark that was
+/// persisted to `STATE_FILE` by a previous run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum HighWaterMarkPolicy {
+    /// Wait for the clock to pass the high-water mark before serving requests
+    Wait,
+    /// Refuse to start
+    Fail,
+}
+
+/// Reads the high-water mark from `path`, returning `None` if the file doesn't exist yet.
+///
+/// The file contains the number of milliseconds since the UNIX epoch (not the configured
+/// `EPOCH`) of the last timestamp that may have been issued, so that it stays human readable.
+pub(crate) fn read_high_water_mark(path: &Path) -> io::Result<Option<SystemTime>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    let unix_ms = contents.trim().parse::<u64>().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("expected a UNIX timestamp in milliseconds, found {contents:?}"),
+        )
+    })?;
+    Ok(Some(UNIX_EPOCH + Duration::from_millis(unix_ms)))
+}
+
+/// Atomically replaces the high-water mark at `path`, so that a crash mid-write can never leave
+/// a truncated file behind.
+pub(crate) fn write_high_water_mark(path: &Path, mark: SystemTime) -> io::Result<()> {
+    let unix_ms = mark
+        .duration_since(UNIX_EPOCH)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?
+        .as_millis();
+
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let mut file = fs::File::create(&tmp_path)?;
+    writeln!(file, "{unix_ms}")?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)
+}
+
+/// Keeps the high-water mark in `STATE_FILE` ahead of every ID the worker issues.
+///
+/// While the worker runs, the mark is periodically moved one write interval into the future, so
+/// that IDs issued between writes are still covered if the process dies without warning. When
+/// this is dropped, the mark is moved back to the last issued timestamp.
+pub(crate) struct StateFile {
+    path: PathBuf,
+    epoch: SystemTime,
+    clock_monitor: Arc<ClockMonitor>,
+    restored_mark_ms: i64,
+    writer: tokio::task::JoinHandle<()>,
+}
+
+impl StateFile {
+    /// Restores the high-water mark into `generator` (waiting for the clock to pass it or
+    /// failing, depending on the configured [`HighWaterMarkPolicy`]) and starts persisting it.
+    pub(crate) async fn open(
+        path: &Path,
+        config: &WorkerConfig,
+        generator: &IdGenerator,
+    ) -> Result<StateFile, ConfigError> {
+        let mark = read_high_water_mark(path).map_err(|source| ConfigError::StateFile {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+        let restored_mark_ms = match mark {
+            Some(mark) => {
+                let mark_ms = mark
+                    .duration_since(config.epoch)
+                    .map(|d| d.as_millis() as i64)
+                    .unwrap_or(0);
+                wait_for_high_water_mark(mark_ms, config, "STATE_FILE").await?;
+                generator.restore_high_water_mark(mark_ms);
+                tracing::info!(state_file = ?path, mark_ms, "restored high-water mark from STATE_FILE");
+                mark_ms
+            }
+            None => -1,
+        };
+
+        let writer = tokio::spawn(persist_periodically(
+            path.to_path_buf(),
+            config.epoch,
+            config.state_file_interval,
+            generator.clock_monitor(),
+        ));
+
+        Ok(StateFile {
+            path: path.to_path_buf(),
+            epoch: config.epoch,
+            clock_monitor: generator.clock_monitor(),
+            restored_mark_ms,
+            writer,
+        })
+    }
+}
+
+impl Drop for StateFile {
+    fn drop(&mut self) {
+        self.writer.abort();
+
+        // NOTE(ayubun): the restored mark may be ahead of anything this run issued. since the
+        // generator treats the mark itself as used up, keeping it is always safe
+        let last_issued_ms = self.clock_monitor.last_issued_ms();
+        let mark_ms = last_issued_ms.max(self.restored_mark_ms);
+        if mark_ms < 0 {
+            return;
+        }
+        let mark = self.epoch + Duration::from_millis(mark_ms as u64);
+        if let Err(err) = write_high_water_mark(&self.path, mark) {
+            tracing::warn!(state_file = ?self.path, error = %err, "failed to write STATE_FILE on shutdown");
+        }
+    }
+}
+
+/// Waits for the clock to pass a high-water mark restored from `origin` (or fails, depending on the
+/// configured [`HighWaterMarkPolicy`]).
+pub(crate) async fn wait_for_high_water_mark(
+    mark_ms: i64,
+    config: &WorkerConfig,
+    origin: &'static str,
+) -> Result<(), ConfigError> {
+    let behind_ms = mark_ms - millis_since(config.epoch);
+    if behind_ms < 0 {
+        return Ok(());
+    }
+    match config.high_water_mark_policy {
+        HighWaterMarkPolicy::Wait => {
+            tracing::info!(
+                behind_ms,
+                origin,
+                "clock is behind the high-water mark, waiting for it to catch up"
+            );
+            // NOTE(ayubun): the mark itself may already have been issued, so we need to wait
+            // until the millisecond after it
+            tokio::time::sleep(Duration::from_millis(behind_ms as u64 + 1)).await;
+            while millis_since(config.epoch) <= mark_ms {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+            Ok(())
+        }
+        HighWaterMarkPolicy::Fail => Err(ConfigError::BehindHighWaterMark { behind_ms, origin }),
+    }
+}
+
+async fn persist_periodically(
+    path: PathBuf,
+    epoch: SystemTime,
+    interval: Duration,
+    clock_monitor: Arc<ClockMonitor>,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        // NOTE(ayubun): reserving a full interval ahead means that a crash before the next write
+        // can't leave any issued IDs above the mark
+        let mark_ms = clock_monitor.last_issued_ms().max(millis_since(epoch));
+        let mark = epoch + Duration::from_millis(mark_ms as u64) + interval;
+
+        let write_path = path.clone();
+        let result =
+            tokio::task::spawn_blocking(move || write_high_water_mark(&write_path, mark)).await;
+        if let Ok(Err(err)) = result {
+            tracing::warn!(state_file = ?path, error = %err, "failed to write STATE_FILE");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::{SnowflakeIdGenerator, DEFAULT_LAYOUT};
+
+    fn temp_state_file(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "snowflake-id-worker-{}-{name}.state",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_high_water_mark_round_trip() {
+        let path = temp_state_file("round-trip");
+        assert_eq!(read_high_water_mark(&path).unwrap(), None);
+
+        let mark = UNIX_EPOCH + Duration::from_millis(1420070400000);
+        write_high_water_mark(&path, mark).unwrap();
+        assert_eq!(read_high_water_mark(&path).unwrap(), Some(mark));
+
+        fs::write(&path, "not a timestamp").unwrap();
+        assert!(read_high_water_mark(&path).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_restored_high_water_mark_is_never_reissued() {
+        let mut generator = SnowflakeIdGenerator::new(DEFAULT_LAYOUT, UNIX_EPOCH, 0, 0);
+        let mark_ms = millis_since(UNIX_EPOCH);
+        generator.restore_high_water_mark(mark_ms);
+
+        let id = generator.generate().unwrap();
+        assert!(DEFAULT_LAYOUT.decompose(id).timestamp_ms > mark_ms);
+    }
+}
@@ -0,0 +1,426 @@
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+
+use tokio_stream::{Stream, StreamExt};
+
+use crate::{
+    allocator::{self, Lease, WorkerIdAllocator},
+    clock::{ClockMonitor, ClockRegressionError, ClockRegressionPolicy},
+    generator::IdGenerator,
+    gossip::Gossip,
+    lockfile::WorkerIdLock,
+    metrics::Metrics,
+    state, ConfigError, WorkerConfig, WorkerError,
+};
+
+/// Returned when a [`Worker`] refuses to issue an ID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GenerateError {
+    /// The clock moved backwards
+    ClockRegression(ClockRegressionError),
+    /// The worker's lease on its worker ID was lost, or couldn't be renewed before it expired, so
+    /// another worker may be using the same ID (see [`Worker::with_allocator`])
+    LeaseLost,
+    /// The time since `EPOCH` no longer fits into `TIMESTAMP_BITS`, so no more IDs can be
+    /// generated with this layout
+    TimestampOverflow { timestamp_bits: u8 },
+}
+
+impl From<ClockRegressionError> for GenerateError {
+    fn from(err: ClockRegressionError) -> GenerateError {
+        GenerateError::ClockRegression(err)
+    }
+}
+
+impl fmt::Display for GenerateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GenerateError::ClockRegression(err) => err.fmt(f),
+            GenerateError::LeaseLost => write!(
+                f,
+                "The worker ID lease was lost or couldn't be renewed: refusing to generate IDs"
+            ),
+            GenerateError::TimestampOverflow { timestamp_bits } => write!(
+                f,
+                "The time since EPOCH no longer fits into TIMESTAMP_BITS ({timestamp_bits}): refusing to generate IDs"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GenerateError {}
+
+/// The first instant that can't be written as an RFC 3339 timestamp, 10000-01-01T00:00:00Z.
+const MAX_RFC3339_TIME: Duration = Duration::from_secs(253_402_300_800);
+
+/// Returned when [`Worker::decode`] can't break an ID down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The ID's timestamp is too far from `EPOCH` to be written as an RFC 3339 timestamp (i.e.
+    /// past the year 9999)
+    TimestampOutOfRange { id: i64 },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::TimestampOutOfRange { id } => write!(
+                f,
+                "Invalid id: {id} has a timestamp past the year 9999, so it can't be a snowflake ID"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// The parts of a snowflake ID, as returned by [`Worker::decode`] and `POST /decode`.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct DecodedId {
+    pub id: i64,
+    /// Milliseconds since the configured `EPOCH`
+    pub timestamp_ms: i64,
+    /// The absolute time the ID was generated at, as an RFC 3339 UTC timestamp
+    pub timestamp_utc: String,
+    pub data_center_id: u64,
+    pub worker_id: u64,
+    pub sequence: u64,
+}
+
+/// A snowflake ID generator that can be embedded without the HTTP server.
+///
+/// Cloning a worker is cheap, and every clone shares the same underlying generator, so IDs stay
+/// unique across all of them.
+///
+/// ```
+/// use snowflake_id_worker::{Worker, WorkerConfig};
+///
+/// let config = WorkerConfig::builder()
+///     .worker_id(7)
+///     .data_center_id(3)
+///     .build()
+///     .unwrap();
+/// let worker = Worker::new(config);
+///
+/// let id = worker.generate().unwrap();
+/// assert_eq!(worker.decode(id).unwrap().worker_id, 7);
+/// ```
+#[derive(Clone)]
+pub struct Worker {
+    config: Arc<WorkerConfig>,
+    // NOTE(ayubun): this used to always be an Arc<Mutex<SnowflakeIdGenerator>>. the atomic
+    // generator is the default now, but the mutex one is still around to compare against
+    generator: Arc<IdGenerator>,
+    clock_monitor: Arc<ClockMonitor>,
+    metrics: Arc<Metrics>,
+    starting: Arc<AtomicBool>,
+    shutting_down: Arc<AtomicBool>,
+    lease: Option<Arc<Lease>>,
+    // NOTE(ayubun): never read, but this is held for as long as any clone of the worker is around,
+    // since dropping it unlocks the worker ID slot for the next worker
+    _worker_id_lock: Option<Arc<WorkerIdLock>>,
+    gossip: Option<Arc<Gossip>>,
+}
+
+impl Worker {
+    /// Creates a worker that generates IDs with the worker and data center IDs in `config`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `config` needs a worker ID to be claimed first. See [`Worker::try_new`] for a
+    /// fallible version.
+    pub fn new(config: WorkerConfig) -> Worker {
+        Worker::try_new(config).unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Creates a worker that generates IDs with the worker and data center IDs in `config`,
+    /// returning a [`ConfigError`] if `config` needs a worker ID to be claimed first.
+    ///
+    /// With `WORKER_ID=FROM_LOCKFILE`, this locks a free slot in `LOCKFILE_DIR` and uses its
+    /// worker ID instead. The slot stays locked until the worker (and every clone of it) is
+    /// dropped.
+    ///
+    /// With `WORKER_ID=FROM_LEASE`, the worker has to be created with [`Worker::with_allocator`]
+    /// instead, since the worker ID in `config` is only a placeholder until a lease is claimed.
+    pub fn try_new(mut config: WorkerConfig) -> Result<Worker, ConfigError> {
+        if config.lease_store.is_some() {
+            return Err(ConfigError::LeaseNotClaimed);
+        }
+        let lock = match &config.lockfile_dir {
+            Some(dir) => {
+                let lock = WorkerIdLock::claim(dir, &config)?;
+                config.worker_id = lock.worker_id();
+                Some(Arc::new(lock))
+            }
+            None => None,
+        };
+        Ok(Worker {
+            _worker_id_lock: lock,
+            ..Worker::unchecked(config)
+        })
+    }
+
+    // NOTE(ayubun): this skips the checks in try_new, so it's only for configs whose worker ID has
+    // already been claimed
+    fn unchecked(config: WorkerConfig) -> Worker {
+        let generator = IdGenerator::from_config(&config);
+        Worker::with_generator(config, generator)
+    }
+
+    pub(crate) fn with_generator(
+        config: WorkerConfig,
+        generator: impl Into<IdGenerator>,
+    ) -> Worker {
+        let generator = generator.into();
+        Worker {
+            config: Arc::new(config),
+            clock_monitor: generator.clock_monitor(),
+            metrics: generator.metrics(),
+            generator: Arc::new(generator),
+            starting: Arc::new(AtomicBool::new(false)),
+            shutting_down: Arc::new(AtomicBool::new(false)),
+            lease: None,
+            _worker_id_lock: None,
+            gossip: None,
+        }
+    }
+
+    /// Claims a free worker ID (and data center ID, if [`lease_data_center_id`] is set) from
+    /// `allocator` instead of using the ones in `config`, and keeps renewing the lease in the
+    /// background. If the lease is lost, the worker stops generating IDs.
+    ///
+    /// If the previous holder of the worker ID left a high-water mark that the clock hasn't passed
+    /// yet, this waits for it to (or fails, depending on [`high_water_mark_policy`]).
+    ///
+    /// This must be called from within a tokio runtime.
+    ///
+    /// [`lease_data_center_id`]: crate::WorkerConfigBuilder::lease_data_center_id
+    /// [`high_water_mark_policy`]: crate::WorkerConfigBuilder::high_water_mark_policy
+    pub async fn with_allocator(
+        mut config: WorkerConfig,
+        allocator: Arc<dyn WorkerIdAllocator>,
+    ) -> Result<Worker, WorkerError> {
+        let lease = allocator::claim(allocator, &config).await?;
+        config.data_center_id = lease.identity().data_center_id;
+        config.worker_id = lease.identity().worker_id;
+        let worker = Worker {
+            lease: Some(lease.clone()),
+            ..Worker::unchecked(config)
+        };
+        lease.track(worker.clock_monitor.clone(), worker.config.epoch);
+
+        if let Some(mark) = lease.restored_mark() {
+            let mark_ms = mark
+                .duration_since(worker.config.epoch)
+                .map(|d| d.as_millis() as i64)
+                .unwrap_or(0);
+            state::wait_for_high_water_mark(mark_ms, &worker.config, "the worker ID lease").await?;
+            worker.generator.restore_high_water_mark(mark_ms);
+            tracing::info!(mark_ms, "restored high-water mark from the worker ID lease");
+        }
+        Ok(worker)
+    }
+
+    /// Gives the worker ID back to the allocator, if it was leased. The worker stops generating
+    /// IDs right away.
+    pub async fn release_lease(&self) {
+        if let Some(lease) = &self.lease {
+            lease.release().await;
+        }
+    }
+
+    pub(crate) fn lease(&self) -> Option<&Lease> {
+        self.lease.as_deref()
+    }
+
+    /// Reports peers on `GOSSIP_GROUP` that conflict with this worker in its readiness. The worker
+    /// keeps generating IDs either way, so that clients which don't go through a load balancer
+    /// aren't cut off by a peer that's only misconfigured.
+    pub(crate) fn with_gossip(self, gossip: Arc<Gossip>) -> Worker {
+        Worker {
+            gossip: Some(gossip),
+            ..self
+        }
+    }
+
+    pub(crate) fn gossip(&self) -> Option<&Gossip> {
+        self.gossip.as_deref()
+    }
+
+    // NOTE(ayubun): this is checked both before and after generating, since the lease could run
+    // out partway through (i.e. while waiting for the clock), and IDs issued after that aren't
+    // covered by the high-water mark in the lease
+    fn check_lease(&self) -> Result<(), GenerateError> {
+        match &self.lease {
+            Some(lease) if !lease.is_valid() => Err(GenerateError::LeaseLost),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn config(&self) -> &WorkerConfig {
+        &self.config
+    }
+
+    pub(crate) fn clock_monitor(&self) -> &ClockMonitor {
+        &self.clock_monitor
+    }
+
+    /// Marks the worker as starting (or not), i.e. while it waits for the clock to pass the
+    /// high-water mark in `STATE_FILE`. A starting worker must not generate IDs.
+    pub(crate) fn set_starting(&self, starting: bool) {
+        self.starting.store(starting, Ordering::Relaxed);
+    }
+
+    pub(crate) fn is_starting(&self) -> bool {
+        self.starting.load(Ordering::Relaxed)
+    }
+
+    /// Marks the worker as shutting down, which fails its health check so that no new requests
+    /// get routed to it.
+    pub(crate) fn begin_shutdown(&self) {
+        self.shutting_down.store(true, Ordering::Relaxed);
+    }
+
+    pub(crate) fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub(crate) fn generator(&self) -> &IdGenerator {
+        &self.generator
+    }
+
+    /// Generates a single snowflake ID.
+    pub fn generate(&self) -> Result<i64, GenerateError> {
+        let started_at = Instant::now();
+        self.check_lease()?;
+        let id = self.generator.generate()?;
+        self.check_lease()?;
+        self.metrics.ids_generated.inc();
+        self.observe_request(1, started_at.elapsed());
+        Ok(id)
+    }
+
+    /// Generates `count` snowflake IDs. The mutex generator holds its lock once for the whole
+    /// batch, while the atomic generator claims as many sequence numbers as it can at a time.
+    ///
+    /// This can block while waiting for the clock to catch up, so async code should use
+    /// [`Worker::generate_batch_async`] instead.
+    pub fn generate_batch(&self, count: usize) -> Result<Vec<i64>, GenerateError> {
+        let started_at = Instant::now();
+        let ids = self.generate_chunk(count)?;
+        self.observe_request(count, started_at.elapsed());
+        Ok(ids)
+    }
+
+    /// Generates `count` snowflake IDs without blocking an async runtime. With
+    /// `CLOCK_REGRESSION_POLICY=wait`, a clock that moved backwards can make generating sleep for
+    /// up to `CLOCK_REGRESSION_MAX_WAIT`, so the batch is generated on tokio's blocking thread pool
+    /// instead.
+    ///
+    /// This must be called from within a tokio runtime.
+    pub async fn generate_batch_async(&self, count: usize) -> Result<Vec<i64>, GenerateError> {
+        let started_at = Instant::now();
+        let ids = self.generate_chunk_async(count).await?;
+        self.observe_request(count, started_at.elapsed());
+        Ok(ids)
+    }
+
+    /// Generates part of a request's IDs. The IDs are counted, but observing the request as a
+    /// whole is left to the caller.
+    fn generate_chunk(&self, count: usize) -> Result<Vec<i64>, GenerateError> {
+        self.check_lease()?;
+        let ids = self.generator.generate_batch(count)?;
+        self.check_lease()?;
+        self.metrics.ids_generated.inc_by(count as u64);
+        Ok(ids)
+    }
+
+    async fn generate_chunk_async(&self, count: usize) -> Result<Vec<i64>, GenerateError> {
+        if !self.may_wait_for_clock() {
+            return self.generate_chunk(count);
+        }
+        let worker = self.clone();
+        tokio::task::spawn_blocking(move || worker.generate_chunk(count))
+            .await
+            .unwrap_or_else(|err| std::panic::resume_unwind(err.into_panic()))
+    }
+
+    fn may_wait_for_clock(&self) -> bool {
+        self.config.clock_regression_policy == ClockRegressionPolicy::Wait
+            && !self.config.clock_regression_max_wait.is_zero()
+    }
+
+    /// Lazily generates `count` snowflake IDs in chunks of up to `chunk_size`. Each chunk is only
+    /// generated once it's asked for, so memory stays bounded no matter how large `count` is, and
+    /// the mutex generator is unlocked between chunks so that other callers can get a turn.
+    pub(crate) fn generate_chunks(
+        &self,
+        count: u64,
+        chunk_size: u64,
+    ) -> impl Stream<Item = Result<Vec<i64>, GenerateError>> + Send + 'static {
+        let worker = self.clone();
+        let starts = (0..count).step_by(chunk_size as usize);
+        // NOTE(ayubun): the histograms are per request, so the whole stream is only observed once
+        // its last chunk is generated. time spent waiting on the client to read the previous chunk
+        // isn't spent generating, so it doesn't count towards the duration
+        let generating = Arc::new(Mutex::new(Duration::ZERO));
+        tokio_stream::iter(starts).then(move |start| {
+            let worker = worker.clone();
+            let generating = generating.clone();
+            async move {
+                let len = chunk_size.min(count - start);
+                let started_at = Instant::now();
+                let ids = worker.generate_chunk_async(len as usize).await?;
+                let mut generating = generating.lock().unwrap();
+                *generating += started_at.elapsed();
+                if start + len == count {
+                    worker.observe_request(count as usize, *generating);
+                }
+                Ok(ids)
+            }
+        })
+    }
+
+    fn observe_request(&self, count: usize, generating: Duration) {
+        self.metrics
+            .generate_duration
+            .observe(generating.as_secs_f64());
+        self.metrics.batch_size.observe(count as f64);
+    }
+
+    /// Breaks a snowflake ID back into its parts, using this worker's `EPOCH` and bit layout.
+    ///
+    /// Fails if the ID's timestamp is past the year 9999, which only happens with wide
+    /// `TIMESTAMP_BITS` (and never for an ID that was actually generated).
+    pub fn decode(&self, id: i64) -> Result<DecodedId, DecodeError> {
+        let parts = self.config.layout.decompose(id);
+        // NOTE(ayubun): humantime panics on anything after 9999-12-31 (or before 1970), and 48 or
+        // so timestamp bits are enough to get there
+        let timestamp = self
+            .config
+            .epoch
+            .checked_add(Duration::from_millis(parts.timestamp_ms as u64))
+            .filter(|timestamp| (UNIX_EPOCH..UNIX_EPOCH + MAX_RFC3339_TIME).contains(timestamp))
+            .ok_or(DecodeError::TimestampOutOfRange { id })?;
+        let timestamp_utc = humantime::format_rfc3339_millis(timestamp).to_string();
+
+        Ok(DecodedId {
+            id,
+            timestamp_ms: parts.timestamp_ms,
+            timestamp_utc,
+            data_center_id: parts.data_center_id,
+            worker_id: parts.worker_id,
+            sequence: parts.sequence,
+        })
+    }
+}